@@ -0,0 +1,372 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use reqwest::{header, Response, StatusCode};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::registry::{Descriptor, RegistryClient};
+
+/// 下载中断后自动续传的次数
+const MAX_ATTEMPTS: u32 = 3;
+/// 第一次续传前的等待时间，之后每次加倍
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+/// 未下载完的 blob 的后缀，续传时从文件末尾继续
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// 按 digest 保存 blob 的本地缓存，不同镜像中相同的层只下载一次
+pub struct BlobCache {
+    root: PathBuf,
+}
+
+/// 缓存中的一个 blob
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub digest: String,
+    pub size: u64,
+    /// 最后一次下载或复用的时间
+    pub modified: SystemTime,
+    /// 下载未完成，下次复制时续传
+    pub partial: bool,
+}
+
+impl BlobCache {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// 默认的缓存目录，linux 上为 `~/.cache/dockertool`
+    pub fn default_dir() -> anyhow::Result<PathBuf> {
+        let cache = dirs::cache_dir().context("Failed to get cache dir")?;
+        Ok(cache.join("dockertool"))
+    }
+
+    pub fn open_default() -> anyhow::Result<Self> {
+        Ok(Self::new(Self::default_dir()?))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn blobs_dir(&self) -> PathBuf {
+        self.root.join("blobs").join("sha256")
+    }
+
+    /// 已完整下载并校验过的 blob 路径，只支持 sha256
+    fn blob_path(&self, digest: &str) -> anyhow::Result<PathBuf> {
+        let hex = digest
+            .strip_prefix("sha256:")
+            .filter(|hex| hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .with_context(|| format!("only sha256 blobs can be cached, got {digest}"))?;
+        Ok(self.blobs_dir().join(hex))
+    }
+
+    fn partial_path(&self, digest: &str) -> anyhow::Result<PathBuf> {
+        let mut path = self.blob_path(digest)?.into_os_string();
+        path.push(PARTIAL_SUFFIX);
+        Ok(path.into())
+    }
+
+    /// 缓存中已有的 blob，命中时更新修改时间，prune 按该时间清理
+    pub fn get(&self, digest: &str) -> Option<PathBuf> {
+        let path = self.blob_path(digest).ok()?;
+        let file = std::fs::File::options().write(true).open(&path).ok()?;
+        if let Err(e) = file.set_modified(SystemTime::now()) {
+            tracing::debug!("can not touch {}: {e}", path.display());
+        }
+        Some(path)
+    }
+
+    /// 返回 blob 在缓存中的路径，没有时从 registry 下载，中断时从已下载的位置续传
+    pub async fn fetch(
+        &self,
+        client: &RegistryClient,
+        registry: &str,
+        repository: &str,
+        blob: &Descriptor,
+    ) -> anyhow::Result<PathBuf> {
+        if let Some(path) = self.get(&blob.digest) {
+            tracing::debug!("{} is cached", blob.digest);
+            return Ok(path);
+        }
+        tokio::fs::create_dir_all(self.blobs_dir()).await?;
+
+        let mut attempt = 1;
+        loop {
+            match self.download(client, registry, repository, blob).await {
+                Ok(path) => return Ok(path),
+                Err(e) if attempt < MAX_ATTEMPTS => {
+                    let delay = RETRY_DELAY * 2u32.pow(attempt - 1);
+                    tracing::warn!(
+                        "download {} failed, resuming in {delay:?} ({attempt}/{MAX_ATTEMPTS}): {e}",
+                        blob.digest
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// 下载一次，边下载边计算 sha256，校验通过后才放入缓存
+    async fn download(
+        &self,
+        client: &RegistryClient,
+        registry: &str,
+        repository: &str,
+        blob: &Descriptor,
+    ) -> anyhow::Result<PathBuf> {
+        let path = self.blob_path(&blob.digest)?;
+        let partial = self.partial_path(&blob.digest)?;
+        let mut offset = tokio::fs::metadata(&partial).await.map_or(0, |m| m.len());
+        if blob.size > 0 && offset > blob.size {
+            offset = 0;
+        }
+
+        let mut hasher = Sha256::new();
+        if blob.size == 0 || offset < blob.size {
+            let mut resp = client
+                .blob_response_from(registry, repository, &blob.digest, offset)
+                .await?;
+            let mut file = if offset > 0 && resp.status() == StatusCode::PARTIAL_CONTENT {
+                // 返回的内容不是从文件末尾开始时无法拼接，删除后下次重新下载
+                let start = content_range_start(&resp);
+                if start != Some(offset) {
+                    tokio::fs::remove_file(&partial).await?;
+                    anyhow::bail!(
+                        "registry returned range starting at {} for {} instead of {offset}",
+                        start.map_or("unknown".to_string(), |s| s.to_string()),
+                        blob.digest
+                    );
+                }
+                hash_file(&partial, &mut hasher).await?;
+                tokio::fs::OpenOptions::new().append(true).open(&partial).await?
+            } else {
+                tokio::fs::File::create(&partial).await?
+            };
+            while let Some(bytes) = resp.chunk().await? {
+                hasher.update(&bytes);
+                file.write_all(&bytes).await?;
+            }
+            file.flush().await?;
+        } else {
+            hash_file(&partial, &mut hasher).await?;
+        }
+
+        let actual = hex::encode(hasher.finalize());
+        if blob.digest.strip_prefix("sha256:") != Some(actual.as_str()) {
+            tokio::fs::remove_file(&partial).await?;
+            anyhow::bail!("blob {} does not match its digest, got sha256:{actual}", blob.digest);
+        }
+        tokio::fs::rename(&partial, &path).await?;
+        Ok(path)
+    }
+
+    /// 缓存中的所有 blob，包括未下载完的
+    pub fn entries(&self) -> anyhow::Result<Vec<CacheEntry>> {
+        let dir = match std::fs::read_dir(self.blobs_dir()) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut entries = Vec::new();
+        for entry in dir {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            let (hex, partial) = match name.strip_suffix(PARTIAL_SUFFIX) {
+                Some(hex) => (hex.to_string(), true),
+                None => (name, false),
+            };
+            let digest = format!("sha256:{hex}");
+            // 忽略不是缓存写入的文件
+            if self.blob_path(&digest).is_err() {
+                continue;
+            }
+            entries.push(CacheEntry {
+                digest,
+                size: metadata.len(),
+                modified: metadata.modified()?,
+                partial,
+            });
+        }
+        entries.sort_by_key(|e| std::cmp::Reverse(e.modified));
+        Ok(entries)
+    }
+
+    /// 缓存的 blob 数和总字节数
+    pub fn usage(&self) -> anyhow::Result<(usize, u64)> {
+        let entries = self.entries()?;
+        Ok((entries.len(), entries.iter().map(|e| e.size).sum()))
+    }
+
+    /// 删除超过 older_than 未使用的 blob，未指定时清空缓存，返回删除的数量和字节数
+    pub fn prune(&self, older_than: Option<Duration>) -> anyhow::Result<(usize, u64)> {
+        let now = SystemTime::now();
+        let mut removed = (0, 0);
+        for entry in self.entries()? {
+            let unused = now.duration_since(entry.modified).unwrap_or_default();
+            if older_than.is_some_and(|older_than| unused < older_than) {
+                continue;
+            }
+            let path = if entry.partial {
+                self.partial_path(&entry.digest)?
+            } else {
+                self.blob_path(&entry.digest)?
+            };
+            std::fs::remove_file(path)?;
+            removed.0 += 1;
+            removed.1 += entry.size;
+        }
+        Ok(removed)
+    }
+}
+
+/// 206 响应中 `Content-Range: bytes start-end/size` 的起始位置
+fn content_range_start(resp: &Response) -> Option<u64> {
+    let range = resp.headers().get(header::CONTENT_RANGE)?.to_str().ok()?;
+    let (start, _) = range.strip_prefix("bytes ")?.split_once('-')?;
+    start.trim().parse().ok()
+}
+
+async fn hash_file(path: &Path, hasher: &mut Sha256) -> anyhow::Result<()> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        hasher.update(&buffer[..n]);
+    }
+}
+
+/// 以 1024 为进制的可读大小，如 `1.5 GiB`
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::registry::mock::{descriptor, MockRegistry};
+
+    /// 仓库 app 中只有一个 blob 的 registry
+    async fn serve(blob: &[u8]) -> (Arc<MockRegistry>, String) {
+        let registry = Arc::new(MockRegistry::default());
+        registry.add_blob("app", blob);
+        let addr = registry.serve().await;
+        (registry, addr)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_resumes() {
+        let blob = (0..100u8).collect::<Vec<_>>();
+        let (registry, addr) = serve(&blob).await;
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BlobCache::new(dir.path());
+        let client = RegistryClient::new();
+        let desc = descriptor(&blob);
+
+        // 上次下载到 40 字节时中断
+        std::fs::create_dir_all(cache.blobs_dir()).unwrap();
+        std::fs::write(cache.partial_path(&desc.digest).unwrap(), &blob[..40]).unwrap();
+
+        let path = cache.fetch(&client, &addr, "app", &desc).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), blob);
+        assert_eq!(*registry.ranges.lock().unwrap(), vec![Some("bytes=40-".to_string())]);
+        assert!(!cache.partial_path(&desc.digest).unwrap().exists());
+
+        // 已缓存的 blob 不再请求
+        cache.fetch(&client, &addr, "app", &desc).await.unwrap();
+        assert_eq!(registry.ranges.lock().unwrap().len(), 1);
+        assert_eq!(cache.usage().unwrap(), (1, 100));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_rejects_corrupt_partial() {
+        let blob = vec![7u8; 64];
+        let (registry, addr) = serve(&blob).await;
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BlobCache::new(dir.path());
+        let desc = descriptor(&blob);
+
+        // 损坏的部分内容导致校验失败，删除后重新下载完整内容
+        std::fs::create_dir_all(cache.blobs_dir()).unwrap();
+        std::fs::write(cache.partial_path(&desc.digest).unwrap(), [0u8; 10]).unwrap();
+        let path = cache.fetch(&RegistryClient::new(), &addr, "app", &desc).await.unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), blob);
+        assert_eq!(*registry.ranges.lock().unwrap(), vec![Some("bytes=10-".to_string()), None]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_rejects_misaligned_range() {
+        let blob = (0..100u8).collect::<Vec<_>>();
+        let (registry, addr) = serve(&blob).await;
+        *registry.misaligned_ranges.lock().unwrap() = 1;
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BlobCache::new(dir.path());
+        let desc = descriptor(&blob);
+
+        // 206 的内容不是从 40 开始时不能追加到部分内容后面，重新下载完整内容
+        std::fs::create_dir_all(cache.blobs_dir()).unwrap();
+        std::fs::write(cache.partial_path(&desc.digest).unwrap(), &blob[..40]).unwrap();
+        let path = cache.fetch(&RegistryClient::new(), &addr, "app", &desc).await.unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), blob);
+        assert_eq!(*registry.ranges.lock().unwrap(), vec![Some("bytes=40-".to_string()), None]);
+    }
+
+    #[test]
+    fn test_prune() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BlobCache::new(dir.path());
+        std::fs::create_dir_all(cache.blobs_dir()).unwrap();
+        let old = descriptor(b"old");
+        let new = descriptor(b"new");
+        std::fs::write(cache.blob_path(&old.digest).unwrap(), b"old").unwrap();
+        std::fs::write(cache.partial_path(&new.digest).unwrap(), b"ne").unwrap();
+        let file = std::fs::File::options()
+            .write(true)
+            .open(cache.blob_path(&old.digest).unwrap())
+            .unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(3 * 86400)).unwrap();
+
+        let entries = cache.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].partial);
+        assert_eq!(entries[1].digest, old.digest);
+
+        assert_eq!(cache.prune(Some(Duration::from_secs(86400))).unwrap(), (1, 3));
+        assert_eq!(cache.prune(None).unwrap(), (1, 2));
+        assert!(cache.entries().unwrap().is_empty());
+        assert!(cache.blob_path("sha512:abc").is_err());
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(512), "512 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
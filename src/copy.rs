@@ -0,0 +1,462 @@
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    cache::BlobCache,
+    image::ImageReference,
+    platform::Platform,
+    registry::{sha256_hex, BlobUpload, Descriptor, Manifest, RegistryClient, Upload},
+};
+
+/// 分段上传时每段的大小
+pub const CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// 一次复制中各 blob 的处理结果
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CopySummary {
+    /// 源镜像的 manifest digest
+    pub source_digest: String,
+    /// 目标仓库中推送的 manifest digest，按平台过滤多平台镜像后与源镜像不同
+    pub digest: String,
+    /// 复制的平台 manifest 数，单平台镜像为 0
+    pub manifests: usize,
+    /// 目标仓库中已存在而跳过的 blob 数
+    pub existing: usize,
+    /// 从同一 registry 的其他仓库挂载的 blob 数
+    pub mounted: usize,
+    /// 实际上传的 blob 数
+    pub uploaded: usize,
+    /// 实际上传的字节数
+    pub uploaded_bytes: u64,
+}
+
+/// 不经过 GitHub Actions，直接把镜像从源 registry 复制到目标 registry
+pub struct ImageCopier {
+    source: RegistryClient,
+    target: RegistryClient,
+    /// 目标 registry 中可以挂载 blob 的其他仓库
+    mount_from: Vec<String>,
+    /// 多平台镜像只复制这些平台，为空时复制全部
+    platforms: Vec<Platform>,
+    /// 设置时先把 blob 下载到本地缓存再上传，下载中断后可以续传
+    cache: Option<BlobCache>,
+    chunk_size: usize,
+}
+
+impl ImageCopier {
+    pub fn new(source: RegistryClient, target: RegistryClient) -> Self {
+        Self {
+            source,
+            target,
+            mount_from: Vec::new(),
+            platforms: Vec::new(),
+            cache: None,
+            chunk_size: CHUNK_SIZE,
+        }
+    }
+
+    /// 上传前尝试从这些仓库挂载 blob，源和目标在同一 registry 时总会尝试源仓库
+    pub fn with_mount_from(mut self, repositories: Vec<String>) -> Self {
+        self.mount_from = repositories;
+        self
+    }
+
+    pub fn with_platforms(mut self, platforms: Vec<Platform>) -> Self {
+        self.platforms = platforms;
+        self
+    }
+
+    pub fn with_cache(mut self, cache: BlobCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// 复制镜像，多平台镜像会复制选中平台的 manifest，最后以目标标签推送 manifest
+    /// 没有过滤掉任何平台时推送原始的 index，digest 与源镜像相同，签名仍然有效
+    pub async fn copy(
+        &self,
+        src: &ImageReference,
+        dst: &ImageReference,
+    ) -> anyhow::Result<CopySummary> {
+        let manifest = self
+            .source
+            .get_manifest(&src.registry, &src.repository, src.reference())
+            .await?;
+        let mut summary = CopySummary {
+            source_digest: manifest.digest.clone(),
+            ..Default::default()
+        };
+        let manifest = if manifest.is_index() {
+            let manifest = filter_index(&manifest, &self.platforms)?;
+            for child in manifest.index()?.manifests {
+                let child = self
+                    .source
+                    .get_manifest(&src.registry, &src.repository, &child.digest)
+                    .await?;
+                self.copy_image_blobs(src, dst, &child, &mut summary).await?;
+                self.target
+                    .put_manifest(&dst.registry, &dst.repository, &child.digest, &child)
+                    .await?;
+                summary.manifests += 1;
+            }
+            manifest
+        } else {
+            self.copy_image_blobs(src, dst, &manifest, &mut summary).await?;
+            manifest
+        };
+        summary.digest = self
+            .target
+            .put_manifest(&dst.registry, &dst.repository, dst.reference(), &manifest)
+            .await?;
+        Ok(summary)
+    }
+
+    /// 复制单平台 manifest 引用的 config 和各层
+    async fn copy_image_blobs(
+        &self,
+        src: &ImageReference,
+        dst: &ImageReference,
+        manifest: &Manifest,
+        summary: &mut CopySummary,
+    ) -> anyhow::Result<()> {
+        let image = manifest.image_manifest()?;
+        for blob in std::iter::once(&image.config).chain(&image.layers) {
+            self.copy_blob(src, dst, blob, summary).await?;
+        }
+        Ok(())
+    }
+
+    /// 目标已有时跳过，能挂载时挂载，否则从源仓库流式读取并分段上传
+    async fn copy_blob(
+        &self,
+        src: &ImageReference,
+        dst: &ImageReference,
+        blob: &Descriptor,
+        summary: &mut CopySummary,
+    ) -> anyhow::Result<()> {
+        if self
+            .target
+            .blob_exists(&dst.registry, &dst.repository, &blob.digest)
+            .await?
+        {
+            tracing::debug!("{} already exists in {}", blob.digest, dst.repository);
+            summary.existing += 1;
+            return Ok(());
+        }
+
+        for from in self.mount_candidates(src, dst) {
+            match self
+                .target
+                .start_upload(&dst.registry, &dst.repository, Some((&blob.digest, from)))
+                .await
+            {
+                Ok(Upload::Mounted) => {
+                    tracing::debug!("{} mounted from {from}", blob.digest);
+                    summary.mounted += 1;
+                    return Ok(());
+                }
+                // 挂载失败时 registry 会开始一次普通上传，取消后继续尝试下一个仓库
+                Ok(Upload::Session(session)) => {
+                    tracing::debug!("{} is not mountable from {from}", blob.digest);
+                    if let Err(e) = self.target.cancel_upload(session).await {
+                        tracing::debug!("cancel upload of {} failed: {e}", blob.digest);
+                    }
+                }
+                Err(e) => tracing::debug!("mount {} from {from} failed: {e}", blob.digest),
+            }
+        }
+        // 先下载到缓存再打开上传会话，下载重试期间会话不会过期
+        let cached = match &self.cache {
+            Some(cache) => Some(
+                cache
+                    .fetch(&self.source, &src.registry, &src.repository, blob)
+                    .await?,
+            ),
+            None => None,
+        };
+        let mut upload = match self
+            .target
+            .start_upload(&dst.registry, &dst.repository, None)
+            .await?
+        {
+            Upload::Session(session) => session,
+            Upload::Mounted => unreachable!("upload without mount can not be mounted"),
+        };
+
+        match cached {
+            Some(path) => {
+                let mut file = tokio::fs::File::open(path).await?;
+                loop {
+                    let mut chunk = Vec::with_capacity(self.chunk_size);
+                    let n = (&mut file)
+                        .take(self.chunk_size as u64)
+                        .read_to_end(&mut chunk)
+                        .await?;
+                    if n == 0 {
+                        break;
+                    }
+                    self.target.upload_chunk(&mut upload, chunk).await?;
+                }
+            }
+            None => self.stream_blob(src, blob, &mut upload).await?,
+        }
+        summary.uploaded += 1;
+        summary.uploaded_bytes += upload.offset();
+        self.target.finish_upload(upload, &blob.digest).await
+    }
+
+    /// 不使用缓存时从源仓库边读边上传
+    async fn stream_blob(
+        &self,
+        src: &ImageReference,
+        blob: &Descriptor,
+        upload: &mut BlobUpload,
+    ) -> anyhow::Result<()> {
+        let mut resp = self
+            .source
+            .blob_response(&src.registry, &src.repository, &blob.digest)
+            .await?;
+        let mut hasher = Sha256::new();
+        let mut buffer = Vec::with_capacity(self.chunk_size.min(blob.size as usize));
+        while let Some(bytes) = resp.chunk().await? {
+            hasher.update(&bytes);
+            buffer.extend_from_slice(&bytes);
+            while buffer.len() >= self.chunk_size {
+                let rest = buffer.split_off(self.chunk_size);
+                let chunk = std::mem::replace(&mut buffer, rest);
+                self.target.upload_chunk(upload, chunk).await?;
+            }
+        }
+        self.target.upload_chunk(upload, buffer).await?;
+
+        // 结束上传前校验，避免把损坏的内容推到目标仓库
+        if let Some(expected) = blob.digest.strip_prefix("sha256:") {
+            let actual = hex::encode(hasher.finalize());
+            if actual != expected {
+                anyhow::bail!("blob {} does not match its digest, got sha256:{actual}", blob.digest);
+            }
+        }
+        Ok(())
+    }
+
+    fn mount_candidates<'a>(
+        &'a self,
+        src: &'a ImageReference,
+        dst: &'a ImageReference,
+    ) -> impl Iterator<Item = &'a str> {
+        let same_registry = (src.registry == dst.registry && src.repository != dst.repository)
+            .then_some(src.repository.as_str());
+        same_registry
+            .into_iter()
+            .chain(self.mount_from.iter().map(|r| r.as_str()))
+            .filter(move |r| *r != dst.repository)
+    }
+}
+
+/// 只保留指定平台的 index，未指定平台或所有平台都被选中时原样返回
+/// 重建时只删除 manifests 中的条目，其余字段保持不变
+fn filter_index(manifest: &Manifest, platforms: &[Platform]) -> anyhow::Result<Manifest> {
+    if platforms.is_empty() {
+        return Ok(manifest.clone());
+    }
+    let selected = |entry: &Descriptor| {
+        entry.platform.as_ref().is_some_and(|p| {
+            platforms
+                .iter()
+                .any(|platform| platform.matches(&p.os, &p.architecture, p.variant.as_deref()))
+        })
+    };
+    let entries = manifest.index()?.manifests;
+    let kept = entries
+        .iter()
+        .filter(|entry| selected(entry))
+        .map(|entry| entry.digest.as_str())
+        .collect::<Vec<_>>();
+    if kept.is_empty() {
+        let available = entries
+            .iter()
+            .filter_map(|entry| entry.platform.as_ref())
+            .map(|p| p.to_string())
+            .collect::<Vec<_>>();
+        anyhow::bail!(
+            "{} has none of the requested platforms, available: {}",
+            manifest.digest,
+            available.join(", ")
+        );
+    }
+    if kept.len() == entries.len() {
+        return Ok(manifest.clone());
+    }
+
+    let mut index: serde_json::Value = serde_json::from_slice(&manifest.body)?;
+    if let Some(serde_json::Value::Array(manifests)) = index.get_mut("manifests") {
+        manifests.retain(|entry| {
+            entry
+                .get("digest")
+                .and_then(|digest| digest.as_str())
+                .is_some_and(|digest| kept.contains(&digest))
+        });
+    }
+    let body = serde_json::to_vec_pretty(&index)?;
+    Ok(Manifest {
+        digest: format!("sha256:{}", sha256_hex(&body)),
+        media_type: manifest.media_type.clone(),
+        body,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::registry::{
+        mock::{descriptor, MockRegistry},
+        OCI_INDEX, OCI_MANIFEST,
+    };
+
+    /// 源仓库 app 中有一个 config 和两层的镜像
+    fn source_registry() -> (Arc<MockRegistry>, Vec<Vec<u8>>) {
+        let registry = Arc::new(MockRegistry::default());
+        let blobs = vec![b"{}".to_vec(), vec![1u8; 25], vec![2u8; 10]];
+        for blob in &blobs {
+            registry.add_blob("app", blob);
+        }
+        let manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": OCI_MANIFEST,
+            "config": descriptor(&blobs[0]),
+            "layers": [descriptor(&blobs[1]), descriptor(&blobs[2])],
+        });
+        registry.add_manifest("app", "1.0", OCI_MANIFEST, &serde_json::to_vec(&manifest).unwrap());
+        (registry, blobs)
+    }
+
+    #[tokio::test]
+    async fn test_copy() {
+        let (source, blobs) = source_registry();
+        let source_addr = source.serve().await;
+        let target = Arc::new(MockRegistry::default());
+        let target_addr = target.serve().await;
+
+        let src: ImageReference = format!("{source_addr}/app:1.0").parse().unwrap();
+        let dst: ImageReference = format!("{target_addr}/mirror:1.0").parse().unwrap();
+        let copier = ImageCopier::new(RegistryClient::new(), RegistryClient::new()).with_chunk_size(8);
+
+        let summary = copier.copy(&src, &dst).await.unwrap();
+        assert_eq!((summary.existing, summary.mounted, summary.uploaded), (0, 0, 3));
+        assert_eq!(summary.uploaded_bytes, 37);
+        // 25 字节的层按 8 字节分为 4 段
+        assert_eq!(*target.patches.lock().unwrap(), 1 + 4 + 2);
+        let (_, body) = target.manifests.lock().unwrap()[&("mirror".to_string(), "1.0".to_string())].clone();
+        assert_eq!(summary.digest, format!("sha256:{}", sha256_hex(&body)));
+        for blob in &blobs {
+            let key = ("mirror".to_string(), format!("sha256:{}", sha256_hex(blob)));
+            assert_eq!(target.blobs.lock().unwrap()[&key], *blob);
+        }
+
+        // 再次复制时所有 blob 都已存在
+        let summary = copier.copy(&src, &dst).await.unwrap();
+        assert_eq!((summary.existing, summary.uploaded), (3, 0));
+
+        // 同一 registry 中的其他仓库通过挂载获得 blob
+        let copier = copier.with_mount_from(vec!["mirror".to_string()]);
+        let other: ImageReference = format!("{target_addr}/other:1.0").parse().unwrap();
+        let summary = copier.copy(&src, &other).await.unwrap();
+        assert_eq!((summary.mounted, summary.uploaded), (3, 0));
+
+        // 第一个仓库中没有 blob 时继续从第二个仓库挂载，挂载失败打开的会话都被取消
+        let copier = copier.with_mount_from(vec!["empty".to_string(), "mirror".to_string()]);
+        let second: ImageReference = format!("{target_addr}/second:1.0").parse().unwrap();
+        let summary = copier.copy(&src, &second).await.unwrap();
+        assert_eq!((summary.mounted, summary.uploaded), (3, 0));
+        assert!(target.uploads.lock().unwrap().is_empty());
+
+        // 经过缓存复制时内容相同，缓存中保留所有 blob
+        let dir = tempfile::tempdir().unwrap();
+        let copier = ImageCopier::new(RegistryClient::new(), RegistryClient::new())
+            .with_cache(BlobCache::new(dir.path()))
+            .with_chunk_size(8);
+        let cached: ImageReference = format!("{target_addr}/cached:1.0").parse().unwrap();
+        let summary = copier.copy(&src, &cached).await.unwrap();
+        assert_eq!((summary.uploaded, summary.uploaded_bytes), (3, 37));
+        for blob in &blobs {
+            let key = ("cached".to_string(), format!("sha256:{}", sha256_hex(blob)));
+            assert_eq!(target.blobs.lock().unwrap()[&key], *blob);
+        }
+        assert_eq!(BlobCache::new(dir.path()).usage().unwrap(), (3, 37));
+    }
+
+    /// 在源仓库中加入 linux/amd64 和 linux/arm64 两个平台的 index，标签为 multi
+    fn add_index(registry: &MockRegistry, blobs: &[Vec<u8>]) -> Vec<u8> {
+        let mut entries = Vec::new();
+        for (layer, (os, arch)) in [(&blobs[1], ("linux", "amd64")), (&blobs[2], ("linux", "arm64"))] {
+            let child = serde_json::to_vec(&serde_json::json!({
+                "schemaVersion": 2,
+                "mediaType": OCI_MANIFEST,
+                "config": descriptor(&blobs[0]),
+                "layers": [descriptor(layer)],
+            }))
+            .unwrap();
+            let digest = format!("sha256:{}", sha256_hex(&child));
+            registry.add_manifest("app", &digest, OCI_MANIFEST, &child);
+            entries.push(serde_json::json!({
+                "mediaType": OCI_MANIFEST,
+                "digest": digest,
+                "size": child.len(),
+                "platform": { "os": os, "architecture": arch },
+            }));
+        }
+        let index = serde_json::to_vec(&serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": OCI_INDEX,
+            "manifests": entries,
+            "annotations": { "org.opencontainers.image.source": "https://example.com/app" },
+        }))
+        .unwrap();
+        registry.add_manifest("app", "multi", OCI_INDEX, &index);
+        index
+    }
+
+    #[tokio::test]
+    async fn test_copy_index() {
+        let (source, blobs) = source_registry();
+        let index = add_index(&source, &blobs);
+        let source_addr = source.serve().await;
+        let target = Arc::new(MockRegistry::default());
+        let target_addr = target.serve().await;
+        let src: ImageReference = format!("{source_addr}/app:multi").parse().unwrap();
+
+        // 复制全部平台时 index 原样推送，digest 不变
+        let dst: ImageReference = format!("{target_addr}/full:multi").parse().unwrap();
+        let copier = ImageCopier::new(RegistryClient::new(), RegistryClient::new());
+        let summary = copier.copy(&src, &dst).await.unwrap();
+        assert_eq!(summary.manifests, 2);
+        assert_eq!(summary.digest, format!("sha256:{}", sha256_hex(&index)));
+        assert_eq!(summary.digest, summary.source_digest);
+
+        // 只复制 amd64 时重建 index，保留其他字段
+        let dst: ImageReference = format!("{target_addr}/amd64:multi").parse().unwrap();
+        let copier = copier.with_platforms(vec!["linux/amd64".parse().unwrap()]);
+        let summary = copier.copy(&src, &dst).await.unwrap();
+        assert_eq!((summary.manifests, summary.uploaded), (1, 2));
+        assert_ne!(summary.digest, summary.source_digest);
+        let (media_type, body) = target.manifests.lock().unwrap()[&("amd64".to_string(), "multi".to_string())].clone();
+        assert_eq!(media_type, OCI_INDEX);
+        let pushed: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(pushed["manifests"].as_array().unwrap().len(), 1);
+        assert_eq!(pushed["manifests"][0]["platform"]["architecture"], "amd64");
+        assert_eq!(pushed["annotations"]["org.opencontainers.image.source"], "https://example.com/app");
+        let arm_layer = ("amd64".to_string(), format!("sha256:{}", sha256_hex(&blobs[2])));
+        assert!(!target.blobs.lock().unwrap().contains_key(&arm_layer));
+
+        let copier = copier.with_platforms(vec!["windows/amd64".parse().unwrap()]);
+        let err = copier.copy(&src, &dst).await.unwrap_err();
+        assert!(err.to_string().contains("linux/amd64, linux/arm64"), "{err}");
+    }
+}
@@ -0,0 +1,217 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    image::ImageReference,
+    naming::{self, ImageMapping, MAPPING_SEPARATOR},
+    platform::Platform,
+    provenance::MirrorRecord,
+    registry::RegistryClient,
+    settings::Settings,
+    swr,
+};
+
+/// SWR 中的标签与源镜像比较的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DriftStatus {
+    /// digest 与源镜像一致
+    UpToDate,
+    /// 源镜像的标签已经指向新的 digest
+    Stale,
+    /// 源 registry 中已经没有该标签
+    MissingUpstream,
+    /// 没有同步记录，无法确定源镜像
+    MirrorOnly,
+    /// 访问源 registry 或者列出 SWR 中的标签失败
+    Failed(String),
+}
+
+impl fmt::Display for DriftStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriftStatus::UpToDate => write!(f, "up-to-date"),
+            DriftStatus::Stale => write!(f, "stale"),
+            DriftStatus::MissingUpstream => write!(f, "missing-upstream"),
+            DriftStatus::MirrorOnly => write!(f, "mirror-only"),
+            DriftStatus::Failed(_) => write!(f, "error"),
+        }
+    }
+}
+
+/// SWR 命名空间中一个标签的比较结果
+#[derive(Debug, Clone)]
+pub struct DriftEntry {
+    pub repository: String,
+    /// 列出仓库的标签失败时为空
+    pub tag: String,
+    /// SWR 中标签的 digest
+    pub digest: Option<String>,
+    pub upstream: Option<ImageReference>,
+    pub status: DriftStatus,
+}
+
+impl DriftEntry {
+    /// 重新同步到原来的仓库和标签的映射，如 `nginx:stable=>nginx:stable`
+    pub fn mapping(&self, strategy: naming::NamingStrategy, separator: &str) -> Option<ImageMapping> {
+        let upstream = self.upstream.as_ref()?;
+        let spec = format!("{upstream}{MAPPING_SEPARATOR}{}:{}", self.repository, self.tag);
+        ImageMapping::parse(&spec, strategy, separator).ok()
+    }
+}
+
+/// 由同步记录得到 SWR 中 `仓库名:标签` 对应的源镜像，较新的记录优先
+pub fn history_sources(conf: &Settings, records: &[MirrorRecord]) -> HashMap<String, ImageReference> {
+    let prefix = format!("{}/{}/", swr::registry_host(conf), conf.namespace);
+    let mut sources = HashMap::new();
+    let mut records = records.iter().collect::<Vec<_>>();
+    records.sort_by_key(|r| std::cmp::Reverse(r.timestamp));
+    for record in records {
+        let Some(target) = record.target.strip_prefix(&prefix) else {
+            continue;
+        };
+        if let Ok(source) = record.source.parse() {
+            sources.entry(target.to_string()).or_insert(source);
+        }
+    }
+    sources
+}
+
+/// 比较 SWR 命名空间中的标签与源镜像，repositories 为空时检查命名空间中的所有仓库
+/// 源镜像取自同步记录，没有记录的标签无法确定源镜像，不做比较
+pub async fn check(
+    conf: &Settings,
+    client: &RegistryClient,
+    sources: &HashMap<String, ImageReference>,
+    repositories: &[String],
+    platforms: &[Platform],
+) -> anyhow::Result<Vec<DriftEntry>> {
+    let repositories = if repositories.is_empty() {
+        swr::list_repositories(conf)
+            .await?
+            .into_iter()
+            .filter_map(|r| r.name)
+            .collect()
+    } else {
+        repositories.to_vec()
+    };
+
+    let mut entries = Vec::new();
+    for repository in repositories {
+        // 一个仓库失败时记录下来，继续检查其他仓库
+        let tags = match swr::list_tags(conf, &repository).await {
+            Ok(tags) => tags,
+            Err(e) => {
+                entries.push(DriftEntry {
+                    repository,
+                    tag: String::new(),
+                    digest: None,
+                    upstream: None,
+                    status: DriftStatus::Failed(format!("failed to list tags: {e}")),
+                });
+                continue;
+            }
+        };
+        for tag in tags {
+            let Some(tag_name) = tag.tag else {
+                continue;
+            };
+            let upstream = sources.get(&format!("{repository}:{tag_name}")).cloned();
+            let status = match &upstream {
+                Some(upstream) => compare(client, upstream, tag.digest.as_deref(), platforms).await,
+                None => DriftStatus::MirrorOnly,
+            };
+            entries.push(DriftEntry {
+                repository: repository.clone(),
+                tag: tag_name,
+                digest: tag.digest,
+                upstream,
+                status,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// 先用 HEAD 比较，digest 不同时再获取 manifest 比较各平台的 digest
+async fn compare(
+    client: &RegistryClient,
+    upstream: &ImageReference,
+    digest: Option<&str>,
+    platforms: &[Platform],
+) -> DriftStatus {
+    let head = match client
+        .head_manifest(&upstream.registry, &upstream.repository, upstream.reference())
+        .await
+    {
+        Ok(Some(head)) => head,
+        Ok(None) => return DriftStatus::MissingUpstream,
+        Err(e) => return DriftStatus::Failed(e.to_string()),
+    };
+    if digest == Some(head.digest.as_str()) {
+        return DriftStatus::UpToDate;
+    }
+    let manifest = match client
+        .get_manifest(&upstream.registry, &upstream.repository, &head.digest)
+        .await
+    {
+        Ok(manifest) => manifest,
+        Err(e) => return DriftStatus::Failed(e.to_string()),
+    };
+    match swr::manifest_digests(&manifest, platforms) {
+        Ok(digests) => classify(digest, &digests),
+        Err(e) => DriftStatus::Failed(e.to_string()),
+    }
+}
+
+/// SWR 中的 digest 是源镜像或其某个平台的 digest 时为最新
+fn classify(digest: Option<&str>, upstream: &[String]) -> DriftStatus {
+    match digest {
+        Some(digest) if upstream.iter().any(|d| d == digest) => DriftStatus::UpToDate,
+        _ => DriftStatus::Stale,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::provenance::parse_since;
+
+    #[test]
+    fn test_classify() {
+        let upstream = ["sha256:index".to_string(), "sha256:amd64".to_string()];
+        assert_eq!(classify(Some("sha256:amd64"), &upstream), DriftStatus::UpToDate);
+        assert_eq!(classify(Some("sha256:old"), &upstream), DriftStatus::Stale);
+        assert_eq!(classify(None, &upstream), DriftStatus::Stale);
+    }
+
+    #[test]
+    fn test_history_sources() {
+        let conf = Settings {
+            namespace: "ns".into(),
+            region: "cn-south-1".into(),
+            ..Default::default()
+        };
+        let record = |source: &str, target: &str, timestamp: &str| MirrorRecord {
+            requester: "alice".into(),
+            source: source.into(),
+            digest: None,
+            target: format!("swr.cn-south-1.myhuaweicloud.com/ns/{target}"),
+            timestamp: parse_since(timestamp).unwrap(),
+        };
+        let records = [
+            record("ghcr.io/foo/bar:1.0", "bar:1.0", "2024-12-01"),
+            record("quay.io/foo/bar:1.0", "bar:1.0", "2024-12-02"),
+        ];
+        let sources = history_sources(&conf, &records);
+        assert_eq!(sources["bar:1.0"].to_string(), "quay.io/foo/bar:1.0");
+
+        let entry = DriftEntry {
+            repository: "bar".into(),
+            tag: "1.0".into(),
+            digest: None,
+            upstream: Some(sources["bar:1.0"].clone()),
+            status: DriftStatus::Stale,
+        };
+        let mapping = entry.mapping(naming::NamingStrategy::Flatten, "_").unwrap();
+        assert_eq!(mapping.target_repository(), ("bar".into(), "1.0".into()));
+    }
+}
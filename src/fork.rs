@@ -0,0 +1,535 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use crypto_box::{aead::OsRng, PublicKey};
+use octocrab::{models::repos::secrets::CreateRepositorySecret, models::Repository, Octocrab};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    remote::RepoLocation,
+    settings::Settings,
+    swr,
+};
+
+/// 上游 pusher 仓库
+pub const UPSTREAM_OWNER: &str = "kingzcheung";
+pub const UPSTREAM_REPO: &str = "docker_image_pusher";
+
+/// pusher workflow 读取的 secrets，沿用上游 workflow 中的名称
+pub const SECRET_REGISTRY: &str = "ALIYUN_REGISTRY";
+pub const SECRET_NAMESPACE: &str = "ALIYUN_NAME_SPACE";
+pub const SECRET_USER: &str = "ALIYUN_REGISTRY_USER";
+pub const SECRET_PASSWORD: &str = "ALIYUN_REGISTRY_PASSWORD";
+pub const REQUIRED_SECRETS: [&str; 4] = [SECRET_REGISTRY, SECRET_NAMESPACE, SECRET_USER, SECRET_PASSWORD];
+
+/// 等待 fork 创建完成的最长时间
+const FORK_READY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// token 需要的 scope
+pub const REQUIRED_SCOPES: [&str; 2] = ["repo", "workflow"];
+
+/// doctor 的一项检查结果
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &str, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            passed: true,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn fail(name: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            passed: false,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// 无法创建 github 客户端时的检查结果，通常是 token 或 github app 的配置不完整
+pub fn credentials_check(e: &anyhow::Error) -> Check {
+    Check::fail(
+        "credentials",
+        format!("can not create github client: {e}"),
+        "set github_token, or github_app_id, github_app_private_key_path and github_app_installation_id in the config",
+    )
+}
+
+/// 同步上游的结果
+#[derive(Debug)]
+pub struct UpdateResult {
+    /// `fast-forward`、`merge` 或 `none`
+    pub merge_type: String,
+    pub message: String,
+    /// 同步进来的上游提交 (sha, 提交信息的第一行)
+    pub commits: Vec<(String, String)>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MergeUpstream {
+    message: String,
+    merge_type: Option<String>,
+}
+
+/// 使用仓库公钥以 libsodium sealed box 加密 secret，返回 base64 编码的密文
+pub fn seal_secret(public_key: &str, value: &str) -> anyhow::Result<String> {
+    let key = STANDARD
+        .decode(public_key)
+        .context("repository public key is not valid base64")?;
+    let key = PublicKey::from_slice(&key).map_err(|_| anyhow::anyhow!("repository public key should be 32 bytes"))?;
+    let sealed = key
+        .seal(&mut OsRng, value.as_bytes())
+        .map_err(|e| anyhow::anyhow!("seal secret failed: {e}"))?;
+    Ok(STANDARD.encode(sealed))
+}
+
+/// fork 上游 pusher 仓库到当前用户或组织下，开启 actions 并写入 SWR 凭证
+pub async fn init(
+    octocrab: &Octocrab,
+    conf: &Settings,
+    upstream: &RepoLocation,
+    organization: Option<&str>,
+) -> anyhow::Result<Repository> {
+    let (upstream_owner, upstream_repo) = (upstream.owner.as_str(), upstream.repo.as_str());
+    let upstream = octocrab.repos(upstream_owner, upstream_repo);
+    let mut fork = upstream.create_fork();
+    if let Some(organization) = organization {
+        fork = fork.organization(organization);
+    }
+    let repo = fork.send().await?;
+    let owner = repo
+        .owner
+        .as_ref()
+        .map(|o| o.login.clone())
+        .context("fork has no owner")?;
+    let name = repo.name.clone();
+    cliclack::log::step(format!("forked {upstream_owner}/{upstream_repo} to {owner}/{name}"))?;
+
+    wait_until_ready(octocrab, &owner, &name).await?;
+
+    enable_actions(octocrab, &owner, &name).await?;
+    cliclack::log::step("enabled github actions")?;
+
+    let (user, password) = swr::login_credentials(conf);
+    let secrets = [
+        (SECRET_REGISTRY, swr::registry_host(conf)),
+        (SECRET_NAMESPACE, conf.namespace.clone()),
+        (SECRET_USER, user),
+        (SECRET_PASSWORD, password),
+    ];
+    let public_key = octocrab.repos(&owner, &name).secrets().get_public_key().await?;
+    for (secret, value) in secrets {
+        let encrypted_value = seal_secret(&public_key.key, &value)?;
+        octocrab
+            .repos(&owner, &name)
+            .secrets()
+            .create_or_update_secret(
+                secret,
+                &CreateRepositorySecret {
+                    encrypted_value: &encrypted_value,
+                    key_id: &public_key.key_id,
+                },
+            )
+            .await?;
+    }
+    cliclack::log::step(format!("wrote secrets {}", REQUIRED_SECRETS.join(", ")))?;
+
+    Ok(repo)
+}
+
+/// github 异步创建 fork，等到能读取到仓库内容为止
+async fn wait_until_ready(octocrab: &Octocrab, owner: &str, repo: &str) -> anyhow::Result<()> {
+    let deadline = tokio::time::Instant::now() + FORK_READY_TIMEOUT;
+    loop {
+        let ready = octocrab
+            .repos(owner, repo)
+            .get_content()
+            .path("images.txt")
+            .send()
+            .await
+            .is_ok();
+        if ready {
+            return Ok(());
+        }
+        if tokio::time::Instant::now() >= deadline {
+            anyhow::bail!("fork {owner}/{repo} is not ready after {}s", FORK_READY_TIMEOUT.as_secs());
+        }
+        tokio::time::sleep(Duration::from_secs(3)).await;
+    }
+}
+
+/// 开启仓库的 actions，fork 下的 workflow 默认是禁用的，需要逐个开启
+async fn enable_actions(octocrab: &Octocrab, owner: &str, repo: &str) -> anyhow::Result<()> {
+    let route = format!("/repos/{owner}/{repo}/actions/permissions");
+    let resp = octocrab
+        ._put(route, Some(&json!({ "enabled": true, "allowed_actions": "all" })))
+        .await?;
+    octocrab::map_github_error(resp).await?;
+
+    let workflows = octocrab.workflows(owner, repo).list().send().await?;
+    for workflow in workflows.items.into_iter().filter(|w| w.state != "active") {
+        let route = format!("/repos/{owner}/{repo}/actions/workflows/{}/enable", workflow.id);
+        let resp = octocrab._put(route, None::<&()>).await?;
+        octocrab::map_github_error(resp).await?;
+    }
+    Ok(())
+}
+
+/// 检查 pusher 仓库是否能正常工作：token scope、fork 状态、actions、secrets 以及是否落后上游，web 为 github 网页地址
+pub async fn doctor(
+    octocrab: &Octocrab,
+    web: &str,
+    owner: &str,
+    repo: &str,
+    app_auth: bool,
+) -> anyhow::Result<Vec<Check>> {
+    // github app 的 installation token 没有 scope，权限由后面的检查确认
+    let mut checks = if app_auth {
+        vec![Check::pass("token", "github app installation, permissions are checked by the steps below")]
+    } else {
+        vec![check_scopes(octocrab, web).await]
+    };
+
+    let repository = match octocrab.repos(owner, repo).get().await {
+        Ok(repository) => repository,
+        Err(e) => {
+            checks.push(Check::fail(
+                "repository",
+                format!("{owner}/{repo} is not accessible: {e}"),
+                "run `dockertool pusher init` or check github_pusher_repo in the config",
+            ));
+            return Ok(checks);
+        }
+    };
+
+    let parent = repository.parent.as_deref();
+    checks.push(match parent {
+        Some(parent) if repository.fork == Some(true) => Check::pass(
+            "fork",
+            format!(
+                "{owner}/{repo} is a fork of {}",
+                parent.full_name.clone().unwrap_or_default()
+            ),
+        ),
+        _ => Check::fail(
+            "fork",
+            format!("{owner}/{repo} is not a fork"),
+            format!("fork {web}/{UPSTREAM_OWNER}/{UPSTREAM_REPO} with `dockertool pusher init`"),
+        ),
+    });
+
+    checks.push(check_actions(octocrab, web, owner, repo).await);
+    checks.push(check_secrets(octocrab, owner, repo).await);
+
+    if let Some(parent) = parent {
+        let branch = repository.default_branch.clone().unwrap_or("main".into());
+        checks.push(check_behind(octocrab, parent, owner, &branch).await);
+    }
+
+    Ok(checks)
+}
+
+/// 从 `X-OAuth-Scopes` 中找出缺少的 scope
+fn missing_scopes(scopes: &str) -> Vec<&'static str> {
+    let scopes = scopes.split(',').map(|s| s.trim()).collect::<Vec<_>>();
+    REQUIRED_SCOPES
+        .into_iter()
+        .filter(|required| !scopes.contains(required))
+        .collect()
+}
+
+async fn check_scopes(octocrab: &Octocrab, web: &str) -> Check {
+    let resp = match octocrab._get("/user").await {
+        Ok(resp) => octocrab::map_github_error(resp).await,
+        Err(e) => Err(e),
+    };
+    let resp = match resp {
+        Ok(resp) => resp,
+        Err(e) => {
+            return Check::fail(
+                "token",
+                format!("token is not valid: {}", github_error(&e)),
+                "create a new token and run `dockertool config`",
+            )
+        }
+    };
+
+    // fine-grained token 不返回 scope，只能依赖后续的检查
+    let Some(scopes) = resp.headers().get("x-oauth-scopes") else {
+        return Check::pass("token", "fine-grained token, scopes are checked by the steps below");
+    };
+    let scopes = scopes.to_str().unwrap_or_default();
+    let missing = missing_scopes(scopes);
+    if missing.is_empty() {
+        Check::pass("token", format!("token scopes: {scopes}"))
+    } else {
+        Check::fail(
+            "token",
+            format!("token is missing scopes: {}", missing.join(", ")),
+            format!("add the `repo` and `workflow` scopes to the token on {web}/settings/tokens"),
+        )
+    }
+}
+
+/// 只有接口返回 enabled 为 false 时才是关闭了 actions，请求失败时报告实际的状态
+async fn check_actions(octocrab: &Octocrab, web: &str, owner: &str, repo: &str) -> Check {
+    let route = format!("/repos/{owner}/{repo}/actions/permissions");
+    let permissions: serde_json::Value = match octocrab.get(route, None::<&()>).await {
+        Ok(permissions) => permissions,
+        Err(e) => {
+            return Check::fail(
+                "actions",
+                format!("can not read actions permissions: {}", github_error(&e)),
+                access_hint(&e, web, owner, repo),
+            )
+        }
+    };
+    if permissions["enabled"].as_bool() == Some(false) {
+        return Check::fail(
+            "actions",
+            "github actions are disabled",
+            format!("enable actions on {web}/{owner}/{repo}/actions"),
+        );
+    }
+
+    let workflows = match octocrab.workflows(owner, repo).list().send().await {
+        Ok(workflows) => workflows,
+        Err(e) => {
+            return Check::fail(
+                "actions",
+                format!("can not list workflows: {}", github_error(&e)),
+                access_hint(&e, web, owner, repo),
+            )
+        }
+    };
+    let disabled = workflows
+        .items
+        .iter()
+        .filter(|w| w.state != "active")
+        .map(|w| w.name.clone())
+        .collect::<Vec<_>>();
+    if disabled.is_empty() {
+        Check::pass("actions", "github actions and workflows are enabled")
+    } else {
+        Check::fail(
+            "actions",
+            format!("workflows are disabled: {}", disabled.join(", ")),
+            format!("enable the workflows on {web}/{owner}/{repo}/actions"),
+        )
+    }
+}
+
+/// github 返回的错误带上状态码，其他错误原样输出
+fn github_error(e: &octocrab::Error) -> String {
+    match e {
+        octocrab::Error::GitHub { source, .. } => format!("{} {}", source.status_code, source.message),
+        e => e.to_string(),
+    }
+}
+
+/// 按请求失败的状态给出修复提示
+fn access_hint(e: &octocrab::Error, web: &str, owner: &str, repo: &str) -> String {
+    match e {
+        octocrab::Error::GitHub { source, .. } if source.status_code == StatusCode::FORBIDDEN => {
+            format!("make sure the token has the `repo` and `workflow` scopes and admin access to {owner}/{repo}")
+        }
+        octocrab::Error::GitHub { source, .. } if source.status_code == StatusCode::NOT_FOUND => {
+            format!("{web}/{owner}/{repo} is not visible to the token, check github_pusher_repo in the config")
+        }
+        _ => "check the network and the github urls in the config".to_string(),
+    }
+}
+
+async fn check_secrets(octocrab: &Octocrab, owner: &str, repo: &str) -> Check {
+    let secrets = match octocrab.repos(owner, repo).secrets().get_secrets().await {
+        Ok(secrets) => secrets,
+        Err(e) => {
+            return Check::fail(
+                "secrets",
+                format!("can not list secrets: {e}"),
+                "make sure the token has admin access to the repository",
+            )
+        }
+    };
+    let names = secrets.secrets.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+    let missing = REQUIRED_SECRETS
+        .into_iter()
+        .filter(|required| !names.contains(required))
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        Check::pass("secrets", "all required secrets exist")
+    } else {
+        Check::fail(
+            "secrets",
+            format!("missing secrets: {}", missing.join(", ")),
+            "run `dockertool pusher init` again to write the secrets",
+        )
+    }
+}
+
+async fn check_behind(octocrab: &Octocrab, parent: &Repository, owner: &str, branch: &str) -> Check {
+    let upstream = parent.full_name.clone().unwrap_or(format!("{UPSTREAM_OWNER}/{UPSTREAM_REPO}"));
+    let upstream_branch = parent.default_branch.clone().unwrap_or("main".into());
+    let route = format!("/repos/{upstream}/compare/{upstream_branch}...{owner}:{branch}");
+    let compare: Result<serde_json::Value, _> = octocrab.get(route, None::<&()>).await;
+    match compare.ok().and_then(|v| v["behind_by"].as_u64()) {
+        Some(0) => Check::pass("upstream", format!("up to date with {upstream}")),
+        Some(behind) => Check::fail(
+            "upstream",
+            format!("{behind} commits behind {upstream}"),
+            "run `dockertool pusher update` to sync the fork",
+        ),
+        None => Check::fail(
+            "upstream",
+            format!("can not compare with {upstream}"),
+            "check that the fork shares history with upstream",
+        ),
+    }
+}
+
+/// 使用 merge-upstream 接口同步 fork 的分支，返回合并方式和同步进来的提交，web 为 github 网页地址
+pub async fn update(
+    octocrab: &Octocrab,
+    web: &str,
+    owner: &str,
+    repo: &str,
+    branch: &str,
+) -> anyhow::Result<UpdateResult> {
+    let before = branch_head(octocrab, owner, repo, branch).await?;
+
+    let route = format!("/repos/{owner}/{repo}/merge-upstream");
+    let merged: Result<MergeUpstream, _> = octocrab
+        .post(route, Some(&json!({ "branch": branch })))
+        .await;
+    let merged = match merged {
+        Ok(merged) => merged,
+        Err(octocrab::Error::GitHub { source, .. }) if source.status_code == StatusCode::CONFLICT => {
+            anyhow::bail!(
+                "{owner}/{repo}:{branch} has conflicts with upstream, resolve them on {}/{owner}/{repo}: {}",
+                web,
+                source.message
+            )
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let after = branch_head(octocrab, owner, repo, branch).await?;
+    let mut commits = Vec::new();
+    if before != after {
+        let route = format!("/repos/{owner}/{repo}/compare/{before}...{after}");
+        let compare: serde_json::Value = octocrab.get(route, None::<&()>).await?;
+        for commit in compare["commits"].as_array().into_iter().flatten() {
+            let sha = commit["sha"].as_str().unwrap_or_default().to_string();
+            let summary = commit["commit"]["message"]
+                .as_str()
+                .and_then(|m| m.lines().next())
+                .unwrap_or_default()
+                .to_string();
+            commits.push((sha, summary));
+        }
+    }
+
+    Ok(UpdateResult {
+        merge_type: merged.merge_type.unwrap_or("none".into()),
+        message: merged.message,
+        commits,
+    })
+}
+
+async fn branch_head(octocrab: &Octocrab, owner: &str, repo: &str, branch: &str) -> anyhow::Result<String> {
+    let route = format!("/repos/{owner}/{repo}/branches/{branch}");
+    let branch: serde_json::Value = octocrab.get(route, None::<&()>).await?;
+    branch["commit"]["sha"]
+        .as_str()
+        .map(|sha| sha.to_string())
+        .context("branch has no head commit")
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{http::StatusCode, routing::get, Json, Router};
+    use crypto_box::SecretKey;
+
+    use super::*;
+
+    /// forbidden 没有权限读取 actions 设置，broken 能读取设置但列出 workflow 失败，off 关闭了 actions，其他仓库不存在
+    async fn mock_github() -> Octocrab {
+        let error = |status: StatusCode, message: &'static str| {
+            move || async move { (status, Json(json!({ "message": message }))) }
+        };
+        let app = Router::new()
+            .route(
+                "/repos/owner/forbidden/actions/permissions",
+                get(error(StatusCode::FORBIDDEN, "Resource not accessible by personal access token")),
+            )
+            .route(
+                "/repos/owner/broken/actions/permissions",
+                get(|| async { Json(json!({ "enabled": true })) }),
+            )
+            .route(
+                "/repos/owner/broken/actions/workflows",
+                get(error(StatusCode::INTERNAL_SERVER_ERROR, "Server Error")),
+            )
+            .route(
+                "/repos/owner/off/actions/permissions",
+                get(|| async { Json(json!({ "enabled": false })) }),
+            )
+            .fallback(error(StatusCode::NOT_FOUND, "Not Found"));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        Octocrab::builder().base_uri(format!("http://{addr}")).unwrap().build().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_check_actions() {
+        let octocrab = mock_github().await;
+        let web = "https://github.com";
+
+        let check = check_actions(&octocrab, web, "owner", "forbidden").await;
+        assert!(!check.passed);
+        assert!(check.detail.contains("403"), "{}", check.detail);
+        assert!(check.hint.unwrap().contains("scopes"));
+
+        let check = check_actions(&octocrab, web, "owner", "missing").await;
+        assert!(check.detail.contains("404"), "{}", check.detail);
+        assert!(check.hint.unwrap().contains("github_pusher_repo"));
+
+        let check = check_actions(&octocrab, web, "owner", "broken").await;
+        assert!(check.detail.starts_with("can not list workflows: 500"), "{}", check.detail);
+
+        let check = check_actions(&octocrab, web, "owner", "off").await;
+        assert_eq!(check.detail, "github actions are disabled");
+    }
+
+    #[test]
+    fn test_seal_secret() {
+        let secret_key = SecretKey::generate(&mut OsRng);
+        let public_key = STANDARD.encode(secret_key.public_key().as_bytes());
+
+        let sealed = seal_secret(&public_key, "password").unwrap();
+        let opened = secret_key.unseal(&STANDARD.decode(sealed).unwrap()).unwrap();
+        assert_eq!(opened, b"password");
+    }
+
+    #[test]
+    fn test_missing_scopes() {
+        assert!(missing_scopes("repo, workflow, read:org").is_empty());
+        assert_eq!(missing_scopes("public_repo"), vec!["repo", "workflow"]);
+        assert_eq!(missing_scopes("repo"), vec!["workflow"]);
+    }
+}
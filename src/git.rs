@@ -0,0 +1,306 @@
+use std::{
+    cell::Cell,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use git2::{
+    build::RepoBuilder, Cred, CredentialType, FetchOptions, IndexAddOption, PushOptions,
+    RemoteCallbacks, Repository, ResetType, Signature,
+};
+
+use crate::image::merge_image_lines;
+
+/// 推送被拒绝(远端分支有新提交)时最多尝试的次数
+const MAX_PUSH_ATTEMPTS: usize = 3;
+/// 同一次操作中最多尝试认证的次数，避免 libgit2 反复回调
+const MAX_AUTH_ATTEMPTS: usize = 3;
+
+/// 通过本地 clone 更新 pusher 仓库的 images.txt，适用于只能访问 git 协议的网络
+pub struct GitPusher {
+    url: String,
+    branch: String,
+    path: String,
+    dir: PathBuf,
+}
+
+/// 本地 clone 的缓存目录，`dirs::cache_dir()/dockertool/pusher/<url>`
+pub fn cache_dir_for(url: &str) -> anyhow::Result<PathBuf> {
+    let cache = dirs::cache_dir().context("Failed to get cache dir")?;
+    let name = url
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+        .collect::<String>();
+    Ok(cache.join("dockertool").join("pusher").join(name))
+}
+
+impl GitPusher {
+    pub fn new(url: &str, branch: &str, path: &str, dir: &Path) -> Self {
+        Self {
+            url: url.to_string(),
+            branch: branch.to_string(),
+            path: path.to_string(),
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// 写入 images.txt 并推送，返回提交的 sha。远端有新提交时把待同步的镜像合并到最新内容后重试
+    pub fn push_images(
+        &self,
+        images: &[String],
+        name: Option<String>,
+        email: Option<String>,
+    ) -> anyhow::Result<String> {
+        let repo = self.open_or_clone()?;
+        let signature = signature(&repo, name, email)?;
+        let message = format!("sync {}", images.join(", "));
+
+        for attempt in 1..=MAX_PUSH_ATTEMPTS {
+            self.fetch(&repo)?;
+            let remote_head = repo
+                .find_reference(&format!("refs/remotes/origin/{}", self.branch))?
+                .peel_to_commit()?;
+            repo.reset(remote_head.as_object(), ResetType::Hard, None)?;
+
+            let file = self.dir.join(&self.path);
+            let content = if attempt == 1 {
+                images.join("\n")
+            } else {
+                merge_image_lines(&fs::read_to_string(&file).unwrap_or_default(), images)
+            };
+            fs::write(&file, content)?;
+
+            let mut index = repo.index()?;
+            index.add_all([self.path.as_str()], IndexAddOption::DEFAULT, None)?;
+            index.write()?;
+            let tree = repo.find_tree(index.write_tree()?)?;
+            let oid = repo.commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                &message,
+                &tree,
+                &[&remote_head],
+            )?;
+
+            match self.push(&repo) {
+                Ok(()) => return Ok(oid.to_string()),
+                Err(PushError::Rejected(reason)) => {
+                    tracing::debug!("push rejected (attempt {attempt}): {reason}");
+                }
+                Err(PushError::Other(e)) => return Err(e),
+            }
+        }
+
+        anyhow::bail!(
+            "{} kept changing while pushing, gave up after {MAX_PUSH_ATTEMPTS} attempts; pending images: {}",
+            self.branch,
+            images.join(", ")
+        )
+    }
+
+    fn open_or_clone(&self) -> anyhow::Result<Repository> {
+        if self.dir.join(".git").exists() {
+            let repo = Repository::open(&self.dir)?;
+            // pusher 地址变更后更新 origin
+            if repo.find_remote("origin")?.url() != Some(self.url.as_str()) {
+                repo.remote_set_url("origin", &self.url)?;
+            }
+            return Ok(repo);
+        }
+
+        // 上次 clone 中断时会留下没有 .git 的目录，先 clone 到临时目录，完成后再移动到位
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)
+                .with_context(|| format!("Failed to remove incomplete clone {}", self.dir.display()))?;
+        }
+        let tmp = self.dir.with_extension(format!("clone-{}", std::process::id()));
+        if tmp.exists() {
+            fs::remove_dir_all(&tmp)?;
+        }
+        fs::create_dir_all(&tmp)?;
+        let attempts = Cell::new(0);
+        let mut fetch = FetchOptions::new();
+        fetch.remote_callbacks(callbacks(&attempts));
+        let cloned = RepoBuilder::new()
+            .branch(&self.branch)
+            .fetch_options(fetch)
+            .clone(&self.url, &tmp)
+            .with_context(|| format!("clone {} failed", self.url));
+        if let Err(e) = cloned {
+            let _ = fs::remove_dir_all(&tmp);
+            return Err(e);
+        }
+        fs::rename(&tmp, &self.dir)?;
+        Ok(Repository::open(&self.dir)?)
+    }
+
+    fn fetch(&self, repo: &Repository) -> anyhow::Result<()> {
+        let attempts = Cell::new(0);
+        let mut fetch = FetchOptions::new();
+        fetch.remote_callbacks(callbacks(&attempts));
+        let refspec = format!("+refs/heads/{0}:refs/remotes/origin/{0}", self.branch);
+        repo.find_remote("origin")?
+            .fetch(&[refspec], Some(&mut fetch), None)
+            .with_context(|| format!("fetch {} failed", self.url))?;
+        Ok(())
+    }
+
+    fn push(&self, repo: &Repository) -> Result<(), PushError> {
+        let attempts = Cell::new(0);
+        let rejected = Cell::new(None);
+        let mut cb = callbacks(&attempts);
+        cb.push_update_reference(|_, status| {
+            if let Some(status) = status {
+                rejected.set(Some(status.to_string()));
+            }
+            Ok(())
+        });
+        let mut options = PushOptions::new();
+        options.remote_callbacks(cb);
+
+        let refspec = format!("refs/heads/{0}:refs/heads/{0}", self.branch);
+        let result = repo
+            .find_remote("origin")
+            .and_then(|mut remote| remote.push(&[refspec], Some(&mut options)));
+
+        match result {
+            Err(e) if e.code() == git2::ErrorCode::NotFastForward => Err(PushError::Rejected(e.to_string())),
+            Err(e) => Err(PushError::Other(
+                anyhow::Error::new(e).context(format!("push {} failed", self.url)),
+            )),
+            Ok(()) => match rejected.take() {
+                Some(reason) => Err(PushError::Rejected(reason)),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+enum PushError {
+    Rejected(String),
+    Other(anyhow::Error),
+}
+
+/// ssh 使用 ssh-agent，https 使用 git 配置中的 credential helper
+fn callbacks(attempts: &Cell<usize>) -> RemoteCallbacks<'_> {
+    let mut cb = RemoteCallbacks::new();
+    cb.credentials(move |url, username, allowed| {
+        attempts.set(attempts.get() + 1);
+        if attempts.get() > MAX_AUTH_ATTEMPTS {
+            return Err(git2::Error::from_str(&format!(
+                "authentication failed for {url}, check ssh-agent or git credential helper"
+            )));
+        }
+        if allowed.contains(CredentialType::SSH_KEY) {
+            return Cred::ssh_key_from_agent(username.unwrap_or("git"));
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            let config = git2::Config::open_default()?;
+            return Cred::credential_helper(&config, url, username);
+        }
+        Cred::default()
+    });
+    cb
+}
+
+/// 提交者依次使用参数和仓库的 git 配置
+fn signature(repo: &Repository, name: Option<String>, email: Option<String>) -> anyhow::Result<Signature<'static>> {
+    let config = repo.config()?;
+    let name = match name {
+        Some(name) => name,
+        None => config
+            .get_string("user.name")
+            .context("commit author is not set, set commit_author_name or GIT_AUTHOR_NAME")?,
+    };
+    let email = match email {
+        Some(email) => email,
+        None => config
+            .get_string("user.email")
+            .context("commit author email is not set, set commit_author_email or GIT_AUTHOR_EMAIL")?,
+    };
+    Ok(Signature::now(&name, &email)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// 创建一个带有 images.txt 的 bare 仓库，返回 bare 仓库路径
+    fn bare_repo(root: &Path) -> PathBuf {
+        let bare = root.join("pusher.git");
+        Repository::init_opts(
+            &bare,
+            git2::RepositoryInitOptions::new()
+                .bare(true)
+                .initial_head("main"),
+        )
+        .unwrap();
+
+        let seed = Repository::init(root.join("seed")).unwrap();
+        fs::write(root.join("seed/images.txt"), "alpine").unwrap();
+        let mut index = seed.index().unwrap();
+        index.add_path(Path::new("images.txt")).unwrap();
+        let tree = seed.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::now("seed", "seed@example.com").unwrap();
+        seed.commit(Some("refs/heads/main"), &sig, &sig, "init", &tree, &[])
+            .unwrap();
+        seed.remote("origin", bare.to_str().unwrap())
+            .unwrap()
+            .push(&["refs/heads/main:refs/heads/main"], None)
+            .unwrap();
+        bare
+    }
+
+    fn read_main(bare: &Path) -> String {
+        let repo = Repository::open_bare(bare).unwrap();
+        let commit = repo.find_reference("refs/heads/main").unwrap().peel_to_commit().unwrap();
+        let entry = commit.tree().unwrap().get_path(Path::new("images.txt")).unwrap();
+        let blob = repo.find_blob(entry.id()).unwrap();
+        String::from_utf8(blob.content().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_push_images() {
+        let root = tempfile::tempdir().unwrap();
+        let bare = bare_repo(root.path());
+        let url = bare.to_str().unwrap();
+
+        let pusher = GitPusher::new(url, "main", "images.txt", &root.path().join("cache"));
+        let sha = pusher
+            .push_images(&["nginx:1.27".into()], Some("bot".into()), Some("bot@example.com".into()))
+            .unwrap();
+        assert_eq!(read_main(&bare), "nginx:1.27");
+
+        let repo = Repository::open_bare(&bare).unwrap();
+        let head = repo.find_reference("refs/heads/main").unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.id().to_string(), sha);
+        assert_eq!(head.author().name(), Some("bot"));
+
+        // 缓存的 clone 可以重复使用
+        pusher
+            .push_images(&["redis:7".into()], Some("bot".into()), Some("bot@example.com".into()))
+            .unwrap();
+        assert_eq!(read_main(&bare), "redis:7");
+    }
+
+    #[test]
+    fn test_recovers_from_incomplete_clone() {
+        let root = tempfile::tempdir().unwrap();
+        let bare = bare_repo(root.path());
+        let url = bare.to_str().unwrap();
+
+        // 模拟中断的 clone 留下的没有 .git 的目录
+        let cache = root.path().join("cache");
+        fs::create_dir_all(&cache).unwrap();
+        fs::write(cache.join("images.txt"), "partial").unwrap();
+
+        let pusher = GitPusher::new(url, "main", "images.txt", &cache);
+        pusher
+            .push_images(&["nginx:1.27".into()], Some("bot".into()), Some("bot@example.com".into()))
+            .unwrap();
+        assert_eq!(read_main(&bare), "nginx:1.27");
+        assert!(cache.join(".git").exists());
+    }
+}
@@ -1,62 +1,328 @@
-use octocrab::{models::repos::CommitAuthor, Octocrab};
-
-pub struct PushImage{
-    octocrab: Octocrab,
-    repo: String,
-    owner: String,
-    branch: Option<String>,
-    path: Option<String>,
-}
-
-impl PushImage {
-    pub fn new(token:&str,owner:&str,repo:&str) -> anyhow::Result<Self> {
-        let octocrab = Octocrab::builder()
-            .personal_token(token.to_string())
-            .build()?;
-        let branch = None;
-        let path = None;
-        let repo = repo.to_string();
-        let owner = owner.to_string();
-        
-        Ok(Self { octocrab, repo, owner, branch, path })
-    }
-
-    pub async fn update_image_file(&self, docker_name: &str,git_user_name:Option<String>,git_user_email:Option<String>) -> anyhow::Result<()> {
-        let path = self.path.clone().map_or("images.txt".into(), |v| v);
-        let branch = self.branch.clone().map_or("main".into(), |v| v);
-        let git_config = git2::Config::open_default()?;
-        let git_user_name = git_user_name.unwrap_or(git_config.get_string("user.name")?);
-        let git_user_email = git_user_email.unwrap_or(git_config.get_string("user.email")?);
-
-
-        let c = self.octocrab
-        .repos(self.owner.as_str(), self.repo.as_str())
-        .get_content()
-        .path(path.clone())
-        .r#ref(branch.clone())
-        .send()
-        .await?;
-
-    let sha = &c.items[0].sha;
-    let message = format!("sync {}", docker_name);
-    let content = docker_name;
-    self.octocrab
-        .repos(self.owner.as_str(), self.repo.as_str())
-        .update_file(path, &message, content, sha)
-        .branch(branch)
-        .commiter(CommitAuthor {
-            name: git_user_name.clone(),
-            email: git_user_email.clone(),
-            date: None,
-        })
-        .author(CommitAuthor {
-            name: git_user_name.to_string(),
-            email: git_user_email.to_string(),
-            date: None,
-        })
-        .send()
-        .await?;
+use std::{fmt, str::FromStr};
+
+use crate::platform::Platform;
+
+pub use crate::pusher::github::{github_client, PushImage};
+
+/// docker hub 的 registry
+pub const DOCKER_HUB: &str = "docker.io";
+/// 仓库名的最大长度
+const NAME_MAX_LENGTH: usize = 255;
+/// 标签的最大长度
+const TAG_MAX_LENGTH: usize = 128;
+
+/// 按 distribution reference 语法解析的镜像地址，
+/// `[registry[:port]/]path[:tag][@algorithm:hex]`，docker hub 的镜像补全为 `docker.io/library/`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ImageReference {
+    pub registry: String,
+    pub repository: String,
+    pub tag: Option<String>,
+    pub digest: Option<String>,
+}
+
+impl ImageReference {
+    /// 没有标签和 digest 时使用 latest
+    pub fn tag_or_latest(&self) -> &str {
+        self.tag.as_deref().unwrap_or("latest")
+    }
+
+    /// 拉取 manifest 使用的引用，有 digest 时优先使用 digest
+    pub fn reference(&self) -> &str {
+        self.digest.as_deref().unwrap_or(self.tag_or_latest())
+    }
+
+    /// 不含标签和 digest 的完整仓库名，如 `docker.io/library/nginx`
+    pub fn name(&self) -> String {
+        format!("{}/{}", self.registry, self.repository)
+    }
+
+    /// 仓库路径的最后一段，如 `bitnami/nginx` 中的 `nginx`
+    pub fn short_name(&self) -> &str {
+        self.repository.rsplit('/').next().unwrap_or(&self.repository)
+    }
+}
+
+impl fmt::Display for ImageReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{tag}")?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{digest}")?;
+        }
         Ok(())
     }
 }
 
+impl FromStr for ImageReference {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            anyhow::bail!("image reference is empty");
+        }
+
+        let (name, tag, digest) = split_reference(s)?;
+        if name.is_empty() {
+            anyhow::bail!("invalid image reference `{s}`: repository name is empty");
+        }
+        if name.len() > NAME_MAX_LENGTH {
+            anyhow::bail!("invalid image reference `{s}`: repository name is longer than {NAME_MAX_LENGTH} characters");
+        }
+
+        // 第一段包含 `.`、`:`、大写字母或者是 localhost 时是 registry
+        let (registry, path) = match name.split_once('/') {
+            Some((host, path))
+                if host.contains(['.', ':']) || host == "localhost" || host.chars().any(|c| c.is_ascii_uppercase()) =>
+            {
+                validate_registry(s, host)?;
+                (host.to_string(), path)
+            }
+            _ => (DOCKER_HUB.to_string(), name),
+        };
+        for component in path.split('/') {
+            validate_path_component(s, component)?;
+        }
+
+        let registry = if registry == "index.docker.io" { DOCKER_HUB.to_string() } else { registry };
+        let repository = if registry == DOCKER_HUB && !path.contains('/') {
+            format!("library/{path}")
+        } else {
+            path.to_string()
+        };
+
+        Ok(Self {
+            registry,
+            repository,
+            tag,
+            digest,
+        })
+    }
+}
+
+/// 把 `name[:tag][@digest]` 拆开并校验标签和 digest，name 不做校验
+pub(crate) fn split_reference(s: &str) -> anyhow::Result<(&str, Option<String>, Option<String>)> {
+    let (rest, digest) = match s.split_once('@') {
+        Some((rest, digest)) => (rest, Some(parse_digest(s, digest)?)),
+        None => (s, None),
+    };
+    // 最后一个 `:` 之后没有 `/` 时是标签，否则是 registry 的端口
+    let (name, tag) = match rest.rsplit_once(':') {
+        Some((name, tag)) if !tag.contains('/') => (name, Some(parse_tag(s, tag)?)),
+        _ => (rest, None),
+    };
+    Ok((name, tag, digest))
+}
+
+/// host 由 `.` 分隔的字母数字和 `-` 组成，或者是 `[ipv6]`，可以带端口
+fn validate_registry(reference: &str, registry: &str) -> anyhow::Result<()> {
+    let (host, port) = if let Some(rest) = registry.strip_prefix('[') {
+        let (ip, rest) = rest
+            .split_once(']')
+            .ok_or_else(|| anyhow::anyhow!("invalid image reference `{reference}`: unclosed `[` in registry `{registry}`"))?;
+        if ip.is_empty() || !ip.chars().all(|c| c.is_ascii_hexdigit() || c == ':') {
+            anyhow::bail!("invalid image reference `{reference}`: `{ip}` is not a valid IPv6 address");
+        }
+        (None, rest.strip_prefix(':'))
+    } else {
+        match registry.split_once(':') {
+            Some((host, port)) => (Some(host), Some(port)),
+            None => (Some(registry), None),
+        }
+    };
+
+    if let Some(host) = host {
+        for label in host.split('.') {
+            let valid = !label.is_empty()
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                && !label.starts_with('-')
+                && !label.ends_with('-');
+            if !valid {
+                anyhow::bail!(
+                    "invalid image reference `{reference}`: registry host `{host}` has invalid label `{label}`"
+                );
+            }
+        }
+    }
+    if let Some(port) = port {
+        if port.is_empty() || !port.chars().all(|c| c.is_ascii_digit()) {
+            anyhow::bail!("invalid image reference `{reference}`: registry port `{port}` must be a number");
+        }
+    }
+    Ok(())
+}
+
+/// 路径的每一段由小写字母数字组成，之间可以用 `.`、`_`、`__` 或多个 `-` 连接
+fn validate_path_component(reference: &str, component: &str) -> anyhow::Result<()> {
+    let error = |reason: &str| {
+        anyhow::anyhow!("invalid image reference `{reference}`: path component `{component}` {reason}")
+    };
+    if component.is_empty() {
+        return Err(error("is empty"));
+    }
+    if let Some(c) = component.chars().find(|c| c.is_ascii_uppercase()) {
+        return Err(error(&format!("contains uppercase letter `{c}`, repository names must be lowercase")));
+    }
+
+    let bytes = component.as_bytes();
+    if !bytes[0].is_ascii_alphanumeric() || !bytes[bytes.len() - 1].is_ascii_alphanumeric() {
+        return Err(error("must start and end with a lowercase letter or digit"));
+    }
+    let mut separator = String::new();
+    for c in component.chars() {
+        if c.is_ascii_lowercase() || c.is_ascii_digit() {
+            let valid = matches!(separator.as_str(), "" | "." | "_" | "__") || separator.chars().all(|s| s == '-');
+            if !valid {
+                return Err(error(&format!("has invalid separator `{separator}`")));
+            }
+            separator.clear();
+        } else if matches!(c, '.' | '_' | '-') {
+            separator.push(c);
+        } else {
+            return Err(error(&format!("contains invalid character `{c}`")));
+        }
+    }
+    Ok(())
+}
+
+/// 标签由字母数字、`_`、`.`、`-` 组成，不能以 `.` 或 `-` 开头，最长 128 个字符
+fn parse_tag(reference: &str, tag: &str) -> anyhow::Result<String> {
+    if tag.is_empty() {
+        anyhow::bail!("invalid image reference `{reference}`: tag is empty");
+    }
+    if tag.len() > TAG_MAX_LENGTH {
+        anyhow::bail!("invalid image reference `{reference}`: tag is longer than {TAG_MAX_LENGTH} characters");
+    }
+    if tag.starts_with(['.', '-']) {
+        anyhow::bail!("invalid image reference `{reference}`: tag `{tag}` can not start with `.` or `-`");
+    }
+    if let Some(c) = tag.chars().find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))) {
+        anyhow::bail!("invalid image reference `{reference}`: tag `{tag}` contains invalid character `{c}`");
+    }
+    Ok(tag.to_string())
+}
+
+/// digest 为 `algorithm:hex`，sha256 和 sha512 需要对应长度的小写十六进制
+fn parse_digest(reference: &str, digest: &str) -> anyhow::Result<String> {
+    let Some((algorithm, hex)) = digest.split_once(':') else {
+        anyhow::bail!("invalid image reference `{reference}`: digest `{digest}` must be algorithm:hex, like sha256:...");
+    };
+    let valid_algorithm = algorithm.starts_with(|c: char| c.is_ascii_alphabetic())
+        && algorithm.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '.' | '_' | '-'));
+    if !valid_algorithm {
+        anyhow::bail!("invalid image reference `{reference}`: digest algorithm `{algorithm}` is not valid");
+    }
+    let expected_len = match algorithm {
+        "sha256" => Some(64),
+        "sha512" => Some(128),
+        _ => None,
+    };
+    let valid_hex = !hex.is_empty() && hex.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c));
+    if !valid_hex || expected_len.is_some_and(|len| hex.len() != len) {
+        anyhow::bail!(
+            "invalid image reference `{reference}`: {algorithm} digest must be {} lowercase hex characters",
+            expected_len.map_or("".to_string(), |len| len.to_string())
+        );
+    }
+    Ok(digest.to_string())
+}
+
+/// images.txt 中的一行，指定平台时格式为 `--platform=linux/arm64 nginx:1.27`
+pub fn image_line(image: &str, platform: Option<&Platform>) -> String {
+    match platform {
+        Some(platform) => format!("--platform={platform} {image}"),
+        None => image.to_string(),
+    }
+}
+
+/// 按平台展开为 images.txt 中的多行，未指定平台时只有镜像名一行
+pub fn image_lines(image: &str, platforms: &[Platform]) -> Vec<String> {
+    if platforms.is_empty() {
+        return vec![image_line(image, None)];
+    }
+    platforms.iter().map(|p| image_line(image, Some(p))).collect()
+}
+
+/// 触发 pusher 的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum PushMode {
+    /// 提交 images.txt 触发 workflow
+    #[default]
+    Commit,
+    /// 调用 workflow_dispatch 接口触发 workflow
+    Dispatch,
+    /// 在本地 clone 中提交 images.txt，通过 git 协议推送
+    Git,
+}
+
+/// 把待同步的镜像追加到 images.txt 现有内容中，已存在的行不重复添加
+pub(crate) fn merge_image_lines(current: &str, images: &[String]) -> String {
+    let mut lines = current
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>();
+    for image in images {
+        if !lines.contains(image) {
+            lines.push(image.clone());
+        }
+    }
+    lines.join("\n")
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(s: &str) -> ImageReference {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_image_reference() {
+        let r = parse("nginx");
+        assert_eq!((r.registry.as_str(), r.repository.as_str()), ("docker.io", "library/nginx"));
+        assert_eq!((r.tag.as_deref(), r.reference()), (None, "latest"));
+        assert_eq!(r.to_string(), "docker.io/library/nginx");
+
+        let r = parse("localhost:5000/app:1.0");
+        assert_eq!((r.registry.as_str(), r.repository.as_str()), ("localhost:5000", "app"));
+        assert_eq!(r.tag.as_deref(), Some("1.0"));
+
+        let digest = format!("sha256:{}", "a".repeat(64));
+        let r = parse(&format!("ghcr.io/foo/bar-baz/app__x:v1.2@{digest}"));
+        assert_eq!((r.registry.as_str(), r.repository.as_str()), ("ghcr.io", "foo/bar-baz/app__x"));
+        assert_eq!(r.reference(), digest);
+        assert_eq!(r.short_name(), "app__x");
+        assert_eq!(r.to_string(), format!("ghcr.io/foo/bar-baz/app__x:v1.2@{digest}"));
+
+        let r = parse("index.docker.io/bitnami/nginx:1.27");
+        assert_eq!(r.to_string(), "docker.io/bitnami/nginx:1.27");
+        assert_eq!(parse("[::1]:5000/app").registry, "[::1]:5000");
+    }
+
+    #[test]
+    fn test_parse_image_reference_errors() {
+        let err = |s: &str| s.parse::<ImageReference>().unwrap_err().to_string();
+        assert!(err("").contains("empty"));
+        assert!(err("Nginx").contains("uppercase letter `N`"));
+        assert!(err("nginx:").contains("tag is empty"));
+        assert!(err("nginx:-1").contains("can not start"));
+        assert!(err("foo//bar").contains("path component `` is empty"));
+        assert!(err("foo/bar_-baz").contains("invalid separator `_-`"));
+        assert!(err("nginx@sha256:abc").contains("64 lowercase hex"));
+        assert!(err("nginx@abc").contains("algorithm:hex"));
+        assert!(err("exa_mple.com/app").contains("invalid label `exa_mple`"));
+        assert!(err("localhost:port/app").contains("port `port` must be a number"));
+    }
+
+    #[test]
+    fn test_merge_image_lines() {
+        let images = ["nginx:1.27".to_string(), "redis:7".to_string()];
+        assert_eq!(merge_image_lines("redis:7\n\n", &images), "redis:7\nnginx:1.27");
+    }
+}
@@ -0,0 +1,235 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::{
+    image::ImageReference,
+    platform::Platform,
+    registry::{Descriptor, Manifest, RegistryClient},
+};
+
+/// 镜像 config blob 中 inspect 关心的字段
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct ImageConfig {
+    #[serde(default)]
+    pub created: Option<String>,
+    #[serde(default)]
+    pub architecture: Option<String>,
+    #[serde(default)]
+    pub os: Option<String>,
+    #[serde(default)]
+    pub variant: Option<String>,
+    #[serde(default)]
+    pub config: Option<ContainerConfig>,
+}
+
+/// 容器运行时的默认配置，字段名与 docker 的 config 一致
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerConfig {
+    #[serde(default)]
+    pub labels: Option<BTreeMap<String, String>>,
+    #[serde(default)]
+    pub entrypoint: Option<Vec<String>>,
+    #[serde(default)]
+    pub cmd: Option<Vec<String>>,
+    #[serde(default)]
+    pub env: Option<Vec<String>>,
+    #[serde(default)]
+    pub exposed_ports: Option<BTreeMap<String, serde_json::Value>>,
+    #[serde(default)]
+    pub working_dir: Option<String>,
+    #[serde(default)]
+    pub user: Option<String>,
+}
+
+/// 远程镜像的概要
+#[derive(Debug, Clone)]
+pub struct ImageInfo {
+    /// 按引用获取到的 manifest，多平台镜像时为 index
+    pub manifest: Manifest,
+    /// 多平台镜像包含的平台
+    pub platforms: Vec<String>,
+    /// 展示的单平台 manifest，单平台镜像时与 manifest 相同
+    pub image: Manifest,
+    /// 展示的平台，来自 config
+    pub platform: Option<String>,
+    pub layers: Vec<Descriptor>,
+    /// config 的原始内容
+    pub config_raw: Vec<u8>,
+    pub config: ImageConfig,
+}
+
+impl ImageInfo {
+    /// 各层压缩后的总大小
+    pub fn total_size(&self) -> u64 {
+        self.layers.iter().map(|l| l.size).sum()
+    }
+}
+
+/// 获取镜像的 manifest 和 config，多平台镜像展示指定平台，未指定时为 linux/amd64，没有时取第一个平台
+pub async fn inspect(
+    client: &RegistryClient,
+    image: &ImageReference,
+    platform: Option<&Platform>,
+) -> anyhow::Result<ImageInfo> {
+    let manifest = client
+        .get_manifest(&image.registry, &image.repository, image.reference())
+        .await?;
+    let (platforms, image_manifest) = if manifest.is_index() {
+        let index = manifest.index()?;
+        // buildkit 的 attestation 以 unknown/unknown 平台放在 index 中，不是可运行的镜像
+        let entries = index
+            .manifests
+            .iter()
+            .filter(|m| !is_attestation(m))
+            .collect::<Vec<_>>();
+        let platforms = entries
+            .iter()
+            .filter_map(|m| m.platform.as_ref())
+            .map(|p| p.to_string())
+            .collect::<Vec<_>>();
+        let digest = match platform {
+            Some(platform) => manifest.platform_digest(platform).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{image} has no {platform} image, available: {}",
+                    platforms.join(", ")
+                )
+            })?,
+            None => manifest
+                .platform_digest(&Platform::from_str("linux/amd64")?)
+                .or_else(|| entries.first().map(|m| m.digest.clone()))
+                .ok_or_else(|| anyhow::anyhow!("{image} is an empty index"))?,
+        };
+        let child = client
+            .get_manifest(&image.registry, &image.repository, &digest)
+            .await?;
+        (platforms, child)
+    } else {
+        (Vec::new(), manifest.clone())
+    };
+
+    let image_manifest_body = image_manifest.image_manifest()?;
+    let config_raw = client
+        .get_blob(&image.registry, &image.repository, &image_manifest_body.config.digest)
+        .await?;
+    let config: ImageConfig = serde_json::from_slice(&config_raw)?;
+    let platform = match (&config.os, &config.architecture) {
+        (Some(os), Some(arch)) => Some(match &config.variant {
+            Some(variant) => format!("{os}/{arch}/{variant}"),
+            None => format!("{os}/{arch}"),
+        }),
+        _ => None,
+    };
+
+    Ok(ImageInfo {
+        manifest,
+        platforms,
+        image: image_manifest,
+        platform,
+        layers: image_manifest_body.layers,
+        config_raw,
+        config,
+    })
+}
+
+fn is_attestation(entry: &Descriptor) -> bool {
+    entry
+        .platform
+        .as_ref()
+        .is_some_and(|p| p.os == "unknown" && p.architecture == "unknown")
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::registry::{
+        mock::{descriptor, MockRegistry},
+        OCI_INDEX, OCI_MANIFEST,
+    };
+
+    #[test]
+    fn test_image_config() {
+        let config: ImageConfig = serde_json::from_value(serde_json::json!({
+            "created": "2024-11-20T08:00:00Z",
+            "architecture": "amd64",
+            "os": "linux",
+            "config": {
+                "Env": ["PATH=/usr/bin"],
+                "Entrypoint": ["/docker-entrypoint.sh"],
+                "Cmd": ["nginx", "-g", "daemon off;"],
+                "ExposedPorts": { "80/tcp": {} },
+                "Labels": { "maintainer": "NGINX" }
+            },
+            "rootfs": { "type": "layers", "diff_ids": [] }
+        }))
+        .unwrap();
+        let container = config.config.unwrap();
+        assert_eq!(container.entrypoint.unwrap(), ["/docker-entrypoint.sh"]);
+        assert_eq!(container.env.unwrap(), ["PATH=/usr/bin"]);
+        assert!(container.exposed_ports.unwrap().contains_key("80/tcp"));
+        assert_eq!(container.labels.unwrap()["maintainer"], "NGINX");
+
+        // scratch 镜像的 config 可能没有 config 字段
+        let config: ImageConfig = serde_json::from_str(r#"{"architecture":"amd64","os":"linux"}"#).unwrap();
+        assert!(config.config.is_none());
+    }
+
+    /// 在仓库 app 中以 tag 推送由这些平台组成的 index，每个平台的 config 中记录自己的平台
+    fn add_index(registry: &MockRegistry, tag: &str, platforms: &[(&str, &str)]) {
+        let entries = platforms
+            .iter()
+            .map(|(os, arch)| {
+                let config = serde_json::to_vec(&serde_json::json!({ "os": os, "architecture": arch })).unwrap();
+                registry.add_blob("app", &config);
+                let child = serde_json::to_vec(&serde_json::json!({
+                    "schemaVersion": 2,
+                    "mediaType": OCI_MANIFEST,
+                    "config": descriptor(&config),
+                    "layers": [],
+                }))
+                .unwrap();
+                let digest = format!("sha256:{}", crate::registry::sha256_hex(&child));
+                registry.add_manifest("app", &digest, OCI_MANIFEST, &child);
+                serde_json::json!({
+                    "mediaType": OCI_MANIFEST,
+                    "digest": digest,
+                    "size": child.len(),
+                    "platform": { "os": os, "architecture": arch },
+                })
+            })
+            .collect::<Vec<_>>();
+        let index = serde_json::json!({ "schemaVersion": 2, "mediaType": OCI_INDEX, "manifests": entries });
+        registry.add_manifest("app", tag, OCI_INDEX, &serde_json::to_vec(&index).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_inspect() {
+        let registry = Arc::new(MockRegistry::default());
+        add_index(&registry, "multi", &[("linux", "arm64"), ("linux", "amd64"), ("unknown", "unknown")]);
+        add_index(&registry, "arm", &[("unknown", "unknown"), ("linux", "arm64"), ("linux", "s390x")]);
+        let addr = registry.serve().await;
+        let client = RegistryClient::new();
+        let image = |tag: &str| format!("{addr}/app:{tag}").parse::<ImageReference>().unwrap();
+
+        // 未指定平台时优先 linux/amd64，列表中没有 attestation
+        let info = inspect(&client, &image("multi"), None).await.unwrap();
+        assert_eq!(info.platform.as_deref(), Some("linux/amd64"));
+        assert_eq!(info.platforms, ["linux/arm64", "linux/amd64"]);
+
+        let arm64 = "linux/arm64".parse().unwrap();
+        let info = inspect(&client, &image("multi"), Some(&arm64)).await.unwrap();
+        assert_eq!(info.platform.as_deref(), Some("linux/arm64"));
+
+        // 没有 linux/amd64 时取第一个不是 attestation 的平台
+        let info = inspect(&client, &image("arm"), None).await.unwrap();
+        assert_eq!(info.platform.as_deref(), Some("linux/arm64"));
+        assert_eq!(info.platforms, ["linux/arm64", "linux/s390x"]);
+
+        let windows = "windows/amd64".parse().unwrap();
+        let err = inspect(&client, &image("multi"), Some(&windows)).await.unwrap_err();
+        assert!(err.to_string().ends_with("available: linux/arm64, linux/amd64"), "{err}");
+    }
+}
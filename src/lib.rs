@@ -5,21 +5,40 @@ use std::{
 
 use anyhow::Context;
 use console::style;
+use naming::ImageMapping;
+use remote::RepoLocation;
 use settings::{save_config, Settings};
 use signer::{HttpRequest, Signer};
 
+pub mod cache;
+pub mod copy;
+pub mod drift;
+pub mod fork;
+pub mod git;
 pub mod image;
+pub mod inspect;
+pub mod naming;
+pub mod platform;
+pub mod provenance;
+pub mod pusher;
+pub mod registry;
+pub mod remote;
 pub mod schema;
 pub mod settings;
 pub mod signer;
+pub mod swr;
+pub mod tags;
+pub mod workflow;
 
-pub async fn get_image_info(conf: &Settings,repository:&str) -> anyhow::Result<()> {
+pub async fn get_image_info(conf: &Settings,image:&str) -> anyhow::Result<()> {
     let sign = Signer;
-    // 如果有tag，去除tag
-    let repository = repository.split(":").next().context("repository name should not contain tag")?;
+    // 按命名方式得到 SWR 中的仓库名，不含标签和 digest
+    let mapping = ImageMapping::parse(image, conf.naming_strategy, conf.naming_separator())?;
+    let (repository, _) = mapping.target_repository();
     let url = format!(
-            "https://swr-api.cn-south-1.myhuaweicloud.com/v2/manage/repos?namespace={namespace}&filter=name%3A%3A{repository}",
+            "{endpoint}/v2/manage/repos?namespace={namespace}&filter=name%3A%3A{repository}",
             // "https://swr-api.cn-south-1.myhuaweicloud.com/v2/manage/namespaces/{namespace}/repos/{repository}",
+            endpoint = swr::api_endpoint(conf),
             namespace = conf.namespace.as_str(),
             repository = repository
         );
@@ -48,13 +67,17 @@ pub fn set_config() -> anyhow::Result<()> {
         .validate(|input: &String| {
             if input.is_empty() {
                 Err("Please enter your github pusher repo.")
-            } else if !input.starts_with("https://") {
-                Err("Please enter a github pusher repo url starts with https://.")
+            } else if !RepoLocation::parse(input).is_ok_and(|l| l.host.is_some()) {
+                Err("Please enter a github pusher repo url like https://github.com/owner/repo or git@host:owner/repo.git.")
             } else {
                 Ok(())
             }
         })
         .interact()?;
+    // 仓库地址中的主机即 github 主机，GitHub Enterprise Server 时为公司的主机名
+    let github_host = RepoLocation::parse(&github_pusher_repo)?
+        .host
+        .unwrap_or_default();
 
     let github_token: String = cliclack::input("what is your github token?")
         .placeholder("github_xxx-xxxxxxxx")
@@ -103,9 +126,11 @@ pub fn set_config() -> anyhow::Result<()> {
     let settings = settings::Settings {
         github_token,
         github_pusher_repo,
+        github_host,
         ak,
         sk,
         namespace,
+        ..Default::default()
     };
     let path = config_path()?;
     save_config(&path, settings)?;
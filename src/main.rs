@@ -1,7 +1,32 @@
-use std::path::PathBuf;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::PathBuf,
+    time::Duration,
+};
 
-use clap::{Parser, Subcommand};
-use dockertool::{config_path, get_image_info, image::PushImage, set_config, settings};
+use chrono::{DateTime, Local};
+use clap::{ArgGroup, Parser, Subcommand};
+use console::style;
+use octocrab::Octocrab;
+use dockertool::{
+    cache::{human_size, BlobCache},
+    config_path,
+    copy::ImageCopier,
+    drift::{self, DriftStatus},
+    fork, get_image_info,
+    image::{github_client, image_lines, ImageReference, PushImage, PushMode},
+    inspect,
+    naming::{self, ImageMapping, NamingStrategy},
+    platform::Platform,
+    provenance::{self, HistoryFilter, MirrorRecord, ProvenanceMode},
+    registry::RegistryClient,
+    pusher::{gitea::GiteaPusher, gitlab::GitlabPusher, Backend, Pusher, PusherRepo},
+    remote::{self, GithubUrls, RepoLocation},
+    set_config,
+    settings::{self, Settings},
+    swr,
+    tags::TagSelector,
+};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -32,23 +57,198 @@ enum Commands {
     /// 设置配置
     Config,
     /// 同步镜像
+    #[command(group(ArgGroup::new("tags_or_regex").multiple(true)))]
     Sync {
-        /// 镜像名称
+        /// 镜像名称，可以指定多个
         /// 如 "docker.io/library/nginx:latest"
         /// 或者 "nginx:latest"
-        image: String,
+        /// 使用 "源镜像=>目标镜像" 指定 SWR 中的名称，如 "ghcr.io/foo/bar:1.0=>tools/bar:1.0"
+        #[arg(required = true)]
+        images: Vec<String>,
         /// github 的推送仓库地址,如 abc/docker_image_pusher
         /// 需要 fork [kingzcheung/docker_image_pusher](https://github.com/kingzcheung/docker_image_pusher) 到你自己的账户下
+        /// Gitea/Forgejo 上可以使用完整地址，如 https://git.example.com/abc/docker_image_pusher
+        /// GitLab 上可以使用包含子群组的项目路径，如 group/sub/docker_image_pusher
+        #[arg(short, long)]
+        pusher: Option<String>,
+        /// pusher 仓库所在的平台，默认读取配置或 github
+        #[arg(long, value_enum)]
+        backend: Option<Backend>,
+        /// 触发方式: commit 提交 images.txt，dispatch 调用 workflow_dispatch(gitlab 上触发 pipeline)，git 通过本地 clone 推送
+        #[arg(short, long, value_enum, default_value_t = PushMode::Commit)]
+        mode: PushMode,
+        /// dispatch 模式下的 workflow 文件名，默认读取配置或 docker.yaml
+        #[arg(long)]
+        workflow: Option<String>,
+        /// dispatch 模式下传递镜像列表的 input 名称，默认读取配置或 images，gitlab 的变量名读取配置中的 gitlab_variable
+        #[arg(long)]
+        input: Option<String>,
+        /// git 模式下 pusher 仓库的 clone 地址，默认读取配置或 github 上的 https 地址
+        #[arg(long)]
+        git_url: Option<String>,
+        /// 等待 actions 或 pipeline 运行结束，失败时以非零状态退出
+        #[arg(short, long)]
+        wait: bool,
+        /// 等待 actions 或 pipeline 的超时时间(秒)
+        #[arg(long, default_value_t = 1800)]
+        timeout: u64,
+        /// 推送完成后检查 SWR 中的镜像标签，并与源镜像的 digest 比较
+        #[arg(long)]
+        verify: bool,
+        /// 等待镜像出现在 SWR 中的超时时间(秒)
+        #[arg(long, default_value_t = 600)]
+        verify_timeout: u64,
+        /// 同步指定平台的镜像，如 linux/arm64。pusher 会把镜像推送到同一个标签，因此只能指定一个平台，
+        /// 需要多平台镜像时使用 copy
+        #[arg(long)]
+        platform: Option<Platform>,
+        /// 未指定目标名称时的命名方式，默认读取配置或 flatten
+        #[arg(long, value_enum)]
+        naming: Option<NamingStrategy>,
+        /// keep-path 和 registry-prefix 命名时的连接符，默认读取配置或 `_`
+        #[arg(long)]
+        separator: Option<String>,
+        /// 记录同步历史: log 追加到 mirror-log.jsonl，trailer 写入提交信息，默认读取配置或 off。
+        /// 只有 github 后端的 commit 模式会记录，其他后端和模式忽略该选项
+        #[arg(long, value_enum)]
+        provenance: Option<ProvenanceMode>,
+        /// SWR 中已有相同 digest 的镜像时也重新同步
+        #[arg(long)]
+        force: bool,
+        /// 同步源仓库中符合 semver 范围的所有标签，如 "~16"，此时镜像不写标签，如 "postgres"
+        #[arg(long, value_name = "RANGE", group = "tags_or_regex")]
+        tags: Option<String>,
+        /// 同步源仓库中完整匹配正则表达式的所有标签，可以与 --tags 同时使用
+        #[arg(long, value_name = "REGEX", group = "tags_or_regex")]
+        tag_regex: Option<String>,
+        /// 按 --tags 或 --tag-regex 选择时只同步最新的 N 个标签
+        #[arg(long, value_name = "N", requires = "tags_or_regex")]
+        latest: Option<usize>,
+    },
+    /// 不经过 pusher 仓库，直接从源 registry 复制镜像到 SWR
+    Copy {
+        /// 源镜像，如 "nginx:1.27" 或 "ghcr.io/foo/bar:1.0"
+        src: String,
+        /// 目标镜像，如 "swr.cn-south-1.myhuaweicloud.com/my_namespace/nginx:1.27"
+        /// 目标为 SWR 时使用 AK/SK 获取临时登录凭证
+        dst: String,
+        /// 上传前尝试从目标 registry 中的这些仓库挂载已有的层，如 my_namespace/nginx，可以指定多次
+        #[arg(long, value_name = "REPOSITORY")]
+        mount_from: Vec<String>,
+        /// 多平台镜像只复制指定平台，如 linux/arm64，可以指定多次，默认复制全部平台
+        #[arg(long = "platform", value_name = "PLATFORM")]
+        platforms: Vec<Platform>,
+        /// 不使用本地 blob 缓存，直接从源仓库边读边上传，中断后无法续传
+        #[arg(long)]
+        no_cache: bool,
+    },
+    /// 查看远程镜像的 digest、平台、层和 config，不需要拉取镜像
+    Inspect {
+        /// 镜像，如 "nginx:1.27" 或 "swr.cn-south-1.myhuaweicloud.com/my_namespace/nginx:1.27"
+        image: String,
+        /// 多平台镜像展示的平台，默认 linux/amd64
+        #[arg(long)]
+        platform: Option<Platform>,
+        /// 输出 manifest 的原始 JSON
+        #[arg(long, conflicts_with = "config")]
+        raw: bool,
+        /// 输出 config 的 JSON
+        #[arg(long)]
+        config: bool,
+    },
+    /// 比较 SWR 命名空间中的镜像与源镜像，找出过期的标签。
+    /// 源镜像取自 github pusher 仓库中的同步历史，没有记录的标签显示为 mirror-only
+    Drift {
+        /// 只检查这些仓库，可以指定多次，默认检查命名空间中的所有仓库
+        #[arg(long = "repo", value_name = "REPOSITORY")]
+        repositories: Vec<String>,
+        /// 比较多平台镜像时使用的平台，可以指定多次，默认 linux/amd64
+        #[arg(long = "platform", value_name = "PLATFORM")]
+        platforms: Vec<Platform>,
+        /// 把过期的镜像提交到 pusher 仓库重新同步，此时最多指定一个平台
+        #[arg(long)]
+        sync: bool,
+        /// 只显示过期和有问题的镜像
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// 管理 copy 使用的本地 blob 缓存
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+    /// 查看 pusher 仓库中记录的同步历史
+    History {
+        /// github 的推送仓库地址，默认读取配置
+        #[arg(short, long)]
+        pusher: Option<String>,
+        /// 只显示源镜像或目标镜像包含该字符串的记录
+        #[arg(long)]
+        image: Option<String>,
+        /// 只显示该用户发起的同步
+        #[arg(long)]
+        requester: Option<String>,
+        /// 只显示该时间之后的记录，如 2024-12-01 或 2024-12-01T08:00:00Z
+        #[arg(long)]
+        since: Option<String>,
+        /// 最多显示的记录数
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// 管理 github 上的 pusher 仓库
+    Pusher {
+        #[command(subcommand)]
+        command: PusherCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheCommands {
+    /// 列出缓存的 blob，最近使用的在前
+    Ls,
+    /// 删除缓存的 blob
+    Prune {
+        /// 只删除超过该天数未使用的 blob，默认清空缓存
+        #[arg(long, value_name = "DAYS")]
+        older_than: Option<u64>,
+    },
+    /// 显示缓存占用的空间
+    Du,
+}
+
+#[derive(Subcommand)]
+enum PusherCommands {
+    /// fork pusher 仓库，开启 actions 并写入 SWR 凭证
+    Init {
+        /// fork 到指定组织，默认 fork 到 token 所属的用户下
+        #[arg(long)]
+        org: Option<String>,
+        /// 上游 pusher 仓库，GitHub Enterprise Server 上需要指定实例中的上游仓库
+        #[arg(long, default_value = "kingzcheung/docker_image_pusher")]
+        upstream: String,
+    },
+    /// 检查 pusher 仓库的 token、fork、actions 和 secrets 是否正常
+    Doctor {
+        /// github 的推送仓库地址，默认读取配置
         #[arg(short, long)]
         pusher: Option<String>,
     },
+    /// 同步上游 pusher 仓库的更新到 fork
+    Update {
+        /// github 的推送仓库地址，默认读取配置
+        #[arg(short, long)]
+        pusher: Option<String>,
+        /// 需要同步的分支
+        #[arg(short, long, default_value = "main")]
+        branch: String,
+    },
 }
 
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
     let path = config_path().expect("Home path not found");
-    let settings = settings::load_config(&path).expect("Please set config first");
+    let mut settings = settings::load_config(&path).expect("Please set config first");
 
     // You can check the value provided by positional arguments, or option arguments
     if let Some(name) = cli.name.as_deref() {
@@ -76,15 +276,341 @@ async fn main() {
                 println!("please set config first:{}", e);
             }
         }
-        Some(Commands::Sync { image, pusher }) => {
+        Some(Commands::Sync {
+            images,
+            pusher,
+            backend,
+            mode,
+            workflow,
+            input,
+            git_url,
+            wait,
+            timeout,
+            verify,
+            verify_timeout,
+            platform,
+            naming,
+            separator,
+            provenance,
+            force,
+            tags,
+            tag_regex,
+            latest,
+        }) => {
+            let platforms = platform.as_slice();
+            let strategy = naming.unwrap_or(settings.naming_strategy);
+            let separator = separator.clone().unwrap_or(settings.naming_separator().to_string());
+            let selector = match TagSelector::new(tags.as_deref(), tag_regex.as_deref(), *latest) {
+                Ok(selector) => selector,
+                Err(e) => {
+                    println!("error:{e}");
+                    std::process::exit(1);
+                }
+            };
+            let images = if selector.is_active() {
+                match select_tags(&settings, images, &selector, strategy, &separator).await {
+                    Ok(images) if images.is_empty() => {
+                        println!("no new upstream tags to sync");
+                        return;
+                    }
+                    Ok(images) => images,
+                    Err(e) => {
+                        println!("error:{e}");
+                        std::process::exit(1);
+                    }
+                }
+            } else {
+                images.clone()
+            };
+            let mappings = match images
+                .iter()
+                .map(|spec| ImageMapping::parse(spec, strategy, &separator))
+                .collect::<anyhow::Result<Vec<_>>>()
+            {
+                Ok(mappings) => mappings,
+                Err(e) => {
+                    println!("error:{e}");
+                    std::process::exit(1);
+                }
+            };
+            for (target, sources) in naming::collisions(&mappings) {
+                println!(
+                    "{} {} are all mirrored to {target} and will overwrite each other",
+                    style("warning:").yellow(),
+                    sources.join(", ")
+                );
+            }
+
+            // 按标签选择时已经去掉了 SWR 中已有的标签
+            let mappings = if *force || selector.is_active() {
+                mappings
+            } else {
+                outdated_mappings(&settings, mappings, platforms).await
+            };
+            if mappings.is_empty() {
+                println!("all images are up to date in SWR, use --force to sync again");
+                return;
+            }
 
             let pusher_url = pusher.clone().unwrap_or(settings.github_pusher_repo.clone());
 
-            let (owner, repo) = parse_pusher_args(&pusher_url).unwrap();
+            let workflow = workflow.clone().unwrap_or(settings.pusher_workflow.clone());
+            let input = input.clone().unwrap_or(settings.pusher_workflow_input.clone());
+            let git_url = git_url.clone().unwrap_or(settings.pusher_git_url.clone());
+            let backend = backend.unwrap_or(settings.pusher_backend);
+            let provenance = provenance.unwrap_or(settings.provenance);
+            if provenance != ProvenanceMode::Off && (backend != Backend::Github || *mode != PushMode::Commit) {
+                println!(
+                    "{} sync history is only recorded by the github backend in commit mode",
+                    style("warning:").yellow()
+                );
+            }
+            let records = if provenance == ProvenanceMode::Off {
+                Vec::new()
+            } else {
+                mirror_records(&settings, &mappings, platforms).await
+            };
+            let push_image = match build_pusher(
+                backend,
+                &pusher_url,
+                &settings,
+                &workflow,
+                &input,
+                &git_url,
+                Provenance {
+                    mode: provenance,
+                    records,
+                },
+            ) {
+                Ok(push_image) => push_image,
+                Err(e) => {
+                    println!("error:{e}");
+                    std::process::exit(1);
+                }
+            };
+            let lines = mappings
+                .iter()
+                .flat_map(|m| image_lines(&m.line(strategy), platforms))
+                .collect::<Vec<_>>();
+            let trigger = match push_image.push(*mode, &lines).await {
+                Ok(trigger) => trigger,
+                Err(e) => {
+                    println!("error:{e}");
+                    std::process::exit(1);
+                }
+            };
+            for mapping in &mappings {
+                println!("{} => {}", mapping.source, swr::target_reference(&settings, mapping));
+            }
+
+            if *wait {
+                match push_image.wait_for_run(&trigger, Duration::from_secs(*timeout)).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(failure)) => {
+                        if let Some(job) = failure.job {
+                            println!("failed job: {job}");
+                        }
+                        if let Some(step) = failure.step {
+                            println!("failed step: {step}");
+                        }
+                        for line in failure.log_tail {
+                            println!("{line}");
+                        }
+                        std::process::exit(1);
+                    }
+                    Err(e) => {
+                        println!("error:{e}");
+                        std::process::exit(1);
+                    }
+                }
+            }
 
-            let push_image = PushImage::new(&settings.github_token, &owner, &repo).unwrap();
-            if let Err(e) = push_image.update_image_file(image, None, None).await {
-                println!("error:{e}")
+            if *verify {
+                let timeout = Duration::from_secs(*verify_timeout);
+                for mapping in &mappings {
+                    if let Err(e) = swr::verify_sync(&settings, mapping, platforms, timeout).await {
+                        println!("error:{e}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+        Some(Commands::Pusher { command }) => match command {
+            PusherCommands::Init { org, upstream } => {
+                let client = RepoLocation::parse(upstream)
+                    .and_then(|upstream| Ok((github_octocrab(&location_urls(&upstream, &settings), &settings)?, upstream)));
+                let (octocrab, upstream) = match client {
+                    Ok(client) => client,
+                    Err(e) => {
+                        println!("error:{e}");
+                        std::process::exit(1);
+                    }
+                };
+                let host = upstream.host_or(settings.github_host()).to_string();
+                match fork::init(&octocrab, &settings, &upstream, org.as_deref()).await {
+                    Ok(repo) => {
+                        let html_url = repo.html_url.map(|u| u.to_string()).unwrap_or_default();
+                        settings.github_pusher_repo = html_url.clone();
+                        settings.github_host = host;
+                        settings::save_config(&path, settings).unwrap();
+                        println!("pusher repo is ready: {html_url}");
+                    }
+                    Err(e) => {
+                        println!("error:{e}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            PusherCommands::Doctor { pusher } => {
+                let pusher_url = pusher.clone().unwrap_or(settings.github_pusher_repo.clone());
+                let (urls, owner, repo) = match parse_pusher_args(&pusher_url, &settings) {
+                    Ok(args) => args,
+                    Err(e) => {
+                        println!("error:{e}");
+                        std::process::exit(1);
+                    }
+                };
+                let checks = match github_octocrab(&urls, &settings) {
+                    Ok(octocrab) => {
+                        let app_auth = settings.github_app().is_ok_and(|app| app.is_some());
+                        match fork::doctor(&octocrab, &urls.web, &owner, &repo, app_auth).await {
+                            Ok(checks) => checks,
+                            Err(e) => {
+                                println!("error:{e}");
+                                std::process::exit(1);
+                            }
+                        }
+                    }
+                    // 认证配置不完整时无法访问 github，作为失败的检查输出
+                    Err(e) => vec![fork::credentials_check(&e)],
+                };
+                for check in &checks {
+                    if check.passed {
+                        println!("{} {}: {}", style("✔").green(), check.name, check.detail);
+                    } else {
+                        println!("{} {}: {}", style("✘").red(), check.name, check.detail);
+                    }
+                    if let Some(hint) = &check.hint {
+                        println!("    {}", style(hint).dim());
+                    }
+                }
+                if checks.iter().any(|c| !c.passed) {
+                    std::process::exit(1);
+                }
+            }
+            PusherCommands::Update { pusher, branch } => {
+                let pusher_url = pusher.clone().unwrap_or(settings.github_pusher_repo.clone());
+                let (octocrab, urls, owner, repo) = match pusher_client(&pusher_url, &settings) {
+                    Ok(client) => client,
+                    Err(e) => {
+                        println!("error:{e}");
+                        std::process::exit(1);
+                    }
+                };
+                match fork::update(&octocrab, &urls.web, &owner, &repo, branch).await {
+                    Ok(result) => {
+                        match result.merge_type.as_str() {
+                            "none" => println!("{owner}/{repo}:{branch} is up to date with upstream"),
+                            merge_type => println!("{owner}/{repo}:{branch} updated ({merge_type}): {}", result.message),
+                        }
+                        for (sha, summary) in result.commits {
+                            println!("  {} {summary}", style(&sha[..sha.len().min(7)]).yellow());
+                        }
+                    }
+                    Err(e) => {
+                        println!("error:{e}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+        },
+        Some(Commands::Copy {
+            src,
+            dst,
+            mount_from,
+            platforms,
+            no_cache,
+        }) => {
+            if let Err(e) = copy_image(&settings, src, dst, mount_from, platforms, !*no_cache).await {
+                println!("error:{e}");
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Inspect {
+            image,
+            platform,
+            raw,
+            config,
+        }) => {
+            if let Err(e) = inspect_image(&settings, image, platform.as_ref(), *raw, *config).await {
+                println!("error:{e}");
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Drift {
+            repositories,
+            platforms,
+            sync,
+            quiet,
+        }) => {
+            if let Err(e) = detect_drift(&settings, repositories, platforms, *sync, *quiet).await {
+                println!("error:{e}");
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Cache { command }) => {
+            if let Err(e) = manage_cache(command) {
+                println!("error:{e}");
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::History {
+            pusher,
+            image,
+            requester,
+            since,
+            limit,
+        }) => {
+            let pusher_url = pusher.clone().unwrap_or(settings.github_pusher_repo.clone());
+            let since = match since.as_deref().map(provenance::parse_since).transpose() {
+                Ok(since) => since,
+                Err(e) => {
+                    println!("error:{e}");
+                    std::process::exit(1);
+                }
+            };
+            let filter = HistoryFilter {
+                image: image.clone(),
+                requester: requester.clone(),
+                since,
+            };
+            let (octocrab, urls, owner, repo) = match pusher_client(&pusher_url, &settings) {
+                Ok(client) => client,
+                Err(e) => {
+                    println!("error:{e}");
+                    std::process::exit(1);
+                }
+            };
+            let push_image = PushImage::from_client(octocrab, &owner, &repo).with_urls(&urls);
+            let records = match push_image.history(&filter).await {
+                Ok(records) => records,
+                Err(e) => {
+                    println!("error:{e}");
+                    std::process::exit(1);
+                }
+            };
+            if records.is_empty() {
+                println!("no sync history found in {owner}/{repo}");
+            }
+            for record in records.iter().take(*limit) {
+                println!(
+                    "{} {} {}{} => {}",
+                    style(record.timestamp.format("%Y-%m-%d %H:%M:%S")).dim(),
+                    style(&record.requester).cyan(),
+                    record.source,
+                    record.digest.as_deref().map(|d| format!("@{d}")).unwrap_or_default(),
+                    record.target
+                );
             }
         }
         Some(Commands::Get { image }) => {
@@ -95,18 +621,438 @@ async fn main() {
     }
 }
 
-/// 支持 owner/repo
-/// 支持 github 上的 url，如: https://github.com/kingzcheung/docker_image_pusher
-fn parse_pusher_args(pusher: &str) -> anyhow::Result<(String, String)> {
-    let mut push_path = pusher.trim_end_matches('/').to_string();
-    if push_path.starts_with("https://github.com/") {
-        push_path = push_path.replace("https://github.com/", "");
+/// 未设置 gitlab_url 且 pusher 地址中没有主机时使用的 gitlab
+const DEFAULT_GITLAB_HOST: &str = "gitlab.com";
+
+/// sync 时写入 pusher 仓库的同步历史，只有 github 后端的 commit 模式会记录
+struct Provenance {
+    mode: ProvenanceMode,
+    records: Vec<MirrorRecord>,
+}
+
+/// 按平台创建 pusher，gitea 和 gitlab 的实例地址依次使用配置中的地址和 pusher 地址中的主机
+fn build_pusher(
+    backend: Backend,
+    pusher: &str,
+    settings: &Settings,
+    workflow: &str,
+    input: &str,
+    git_url: &str,
+    provenance: Provenance,
+) -> anyhow::Result<Box<dyn Pusher>> {
+    match backend {
+        Backend::Github => {
+            let (urls, owner, repo) = parse_pusher_args(pusher, settings)?;
+            let push_image = match settings.github_app()? {
+                Some(app) => PushImage::from_app(&urls, &app, &owner, &repo)?,
+                None => PushImage::new(&urls, &settings.github_token, &owner, &repo)?,
+            };
+            let push_image = push_image
+                .with_workflow(workflow, input)
+                .with_commit_author(&settings.commit_author_name, &settings.commit_author_email)
+                .with_git_url(git_url)
+                .with_provenance(provenance.mode, provenance.records);
+            Ok(Box::new(push_image))
+        }
+        Backend::Gitea => {
+            let location = RepoLocation::parse(pusher)?;
+            let base_url = match (settings.gitea_url.as_str(), location.web_base()) {
+                ("", Some(base)) => base,
+                ("", None) => anyhow::bail!("gitea_url is not set, set it or use the full repository url as pusher"),
+                (url, _) => url.to_string(),
+            };
+            let push_image = GiteaPusher::new(&base_url, &settings.gitea_token, &location.owner, &location.repo)
+                .with_workflow(workflow, input)
+                .with_commit_author(&settings.commit_author_name, &settings.commit_author_email)
+                .with_git_url(git_url);
+            Ok(Box::new(push_image))
+        }
+        Backend::Gitlab => {
+            let location = RepoLocation::parse_nested(pusher)?;
+            let base_url = match (settings.gitlab_url.as_str(), location.web_base()) {
+                ("", Some(base)) => base,
+                ("", None) => remote::web_base(DEFAULT_GITLAB_HOST),
+                (url, _) => url.to_string(),
+            };
+            let project = format!("{}/{}", location.owner, location.repo);
+            let push_image = GitlabPusher::new(&base_url, &settings.gitlab_token, &project)
+                .with_trigger_token(&settings.gitlab_trigger_token)
+                .with_variable(&settings.gitlab_variable)
+                .with_commit_author(&settings.commit_author_name, &settings.commit_author_email)
+                .with_git_url(git_url);
+            Ok(Box::new(push_image))
+        }
     }
+}
 
-    let res = push_path.split("/").collect::<Vec<_>>();
-    if res.len() == 2 {
-        return Ok((res[0].to_string(), res[1].to_string()));
+/// 把不带标签的镜像展开为源仓库中选中的各个标签，去掉 SWR 中已有的标签
+/// 支持 `postgres=>db/postgres` 指定目标仓库，目标标签与源标签相同
+async fn select_tags(
+    settings: &Settings,
+    images: &[String],
+    selector: &TagSelector,
+    strategy: NamingStrategy,
+    separator: &str,
+) -> anyhow::Result<Vec<String>> {
+    let client = RegistryClient::new();
+    let mut selected = Vec::new();
+    for spec in images {
+        let (source, target) = match spec.split_once(naming::MAPPING_SEPARATOR) {
+            Some((source, target)) => (source.trim(), Some(target.trim())),
+            None => (spec.trim(), None),
+        };
+        let reference: ImageReference = source.parse()?;
+        if reference.tag.is_some() || reference.digest.is_some() {
+            anyhow::bail!("`{source}` should not have a tag when selecting tags with --tags or --tag-regex");
+        }
+        if target.is_some_and(|target| target.contains(':')) {
+            anyhow::bail!("target in `{spec}` should not have a tag when selecting tags with --tags or --tag-regex");
+        }
+
+        let upstream = client.list_tags(&reference.registry, &reference.repository).await?;
+        let (repository, _) = ImageMapping::parse(spec, strategy, separator)?.target_repository();
+        let existing = match swr::list_tags(settings, &repository).await {
+            Ok(tags) => tags.into_iter().filter_map(|t| t.tag).collect(),
+            // 仓库还不存在时没有已有的标签，其他错误不能当作没有标签，否则会重复同步
+            Err(e) if swr::is_not_found(&e) => {
+                tracing::debug!("{repository} does not exist in SWR: {e}");
+                HashSet::new()
+            }
+            Err(e) => anyhow::bail!("failed to list tags of {repository} in SWR: {e}"),
+        };
+        let tags = selector.select(&upstream, &existing);
+        println!(
+            "{source}: {} matching tags upstream, {} new: {}",
+            upstream.iter().filter(|tag| selector.matches(tag)).count(),
+            tags.len(),
+            tags.join(", ")
+        );
+        selected.extend(tags.iter().map(|tag| match target {
+            Some(target) => format!("{source}:{tag}{}{target}", naming::MAPPING_SEPARATOR),
+            None => format!("{source}:{tag}"),
+        }));
     }
+    Ok(selected)
+}
+
+/// 去掉 SWR 中已经是源镜像 digest 的映射，无法比较时保留
+async fn outdated_mappings(settings: &Settings, mappings: Vec<ImageMapping>, platforms: &[Platform]) -> Vec<ImageMapping> {
+    let mut outdated = Vec::new();
+    for mapping in mappings {
+        match swr::synced_digest(settings, &mapping, platforms).await {
+            Ok(Some(digest)) => println!(
+                "{} {} is up to date in SWR ({digest}), skipped",
+                style("✔").green(),
+                mapping.source
+            ),
+            Ok(None) => outdated.push(mapping),
+            Err(e) => {
+                tracing::debug!("can not compare {} with SWR: {e}", mapping.source);
+                outdated.push(mapping);
+            }
+        }
+    }
+    outdated
+}
+
+/// 解析源镜像的 digest 生成同步记录，源 registry 无法访问时 digest 为空
+async fn mirror_records(settings: &Settings, mappings: &[ImageMapping], platforms: &[Platform]) -> Vec<MirrorRecord> {
+    let mut records = Vec::new();
+    for mapping in mappings {
+        let digest = match swr::source_digests(&mapping.reference, platforms).await {
+            Ok(digests) => digests.into_iter().next(),
+            Err(e) => {
+                println!("{} failed to resolve {}: {e}", style("warning:").yellow(), mapping.source);
+                None
+            }
+        };
+        records.push(MirrorRecord::new(&mapping.reference.to_string(), digest, &swr::target_reference(settings, mapping)));
+    }
+    records
+}
+
+/// 复制镜像并输出各 blob 的处理结果
+async fn copy_image(
+    settings: &Settings,
+    src: &str,
+    dst: &str,
+    mount_from: &[String],
+    platforms: &[Platform],
+    use_cache: bool,
+) -> anyhow::Result<()> {
+    let src: ImageReference = src.parse()?;
+    let dst: ImageReference = dst.parse()?;
+    let target = registry_client(settings, &dst).await?;
+    let mut copier = ImageCopier::new(RegistryClient::new(), target)
+        .with_mount_from(mount_from.to_vec())
+        .with_platforms(platforms.to_vec());
+    if use_cache {
+        copier = copier.with_cache(BlobCache::open_default()?);
+    }
+
+    let spinner = cliclack::spinner();
+    spinner.start(format!("copying {src} to {dst}..."));
+    match copier.copy(&src, &dst).await {
+        Ok(summary) => {
+            spinner.stop(format!("{src} => {dst} ({})", summary.digest));
+            if summary.manifests > 0 {
+                println!("platforms: {} copied", summary.manifests);
+            }
+            if summary.digest != summary.source_digest {
+                println!(
+                    "{} the index was rebuilt for the selected platforms, its digest differs from {}",
+                    style("note:").cyan(),
+                    summary.source_digest
+                );
+            }
+            println!(
+                "blobs: {} uploaded ({}), {} mounted, {} already existed",
+                summary.uploaded,
+                human_size(summary.uploaded_bytes),
+                summary.mounted,
+                summary.existing
+            );
+            Ok(())
+        }
+        Err(e) => {
+            spinner.error(format!("failed to copy {src}"));
+            Err(e)
+        }
+    }
+}
+
+/// 访问 SWR 时使用 AK/SK 获取的临时凭证，其他 registry 匿名访问
+async fn registry_client(settings: &Settings, image: &ImageReference) -> anyhow::Result<RegistryClient> {
+    let client = RegistryClient::new();
+    if image.registry != swr::registry_host(settings) {
+        return Ok(client);
+    }
+    let (username, password) = swr::temporary_login(settings).await?;
+    Ok(client.with_credentials(&username, &password))
+}
+
+/// 输出镜像概要，或者 manifest、config 的 JSON
+async fn inspect_image(
+    settings: &Settings,
+    image: &str,
+    platform: Option<&Platform>,
+    raw: bool,
+    config: bool,
+) -> anyhow::Result<()> {
+    let image: ImageReference = image.parse()?;
+    let client = registry_client(settings, &image).await?;
+    let info = inspect::inspect(&client, &image, platform).await?;
+    if raw {
+        // 指定平台时输出该平台的 manifest，否则输出引用对应的 manifest
+        let manifest = if platform.is_some() { &info.image } else { &info.manifest };
+        println!("{}", String::from_utf8_lossy(&manifest.body));
+        return Ok(());
+    }
+    if config {
+        let value: serde_json::Value = serde_json::from_slice(&info.config_raw)?;
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        return Ok(());
+    }
+
+    let field = |name: &str| style(format!("{name:<12}")).bold();
+    println!("{}{image}", field("Name:"));
+    println!("{}{}", field("Digest:"), info.manifest.digest);
+    println!("{}{}", field("MediaType:"), info.manifest.media_type);
+    if !info.platforms.is_empty() {
+        println!("{}{}", field("Platforms:"), info.platforms.join(", "));
+        println!("{}{}", field("Showing:"), info.image.digest);
+    }
+    if let Some(platform) = &info.platform {
+        println!("{}{platform}", field("Platform:"));
+    }
+    if let Some(created) = &info.config.created {
+        println!("{}{created}", field("Created:"));
+    }
+    let container = info.config.config.clone().unwrap_or_default();
+    if let Some(entrypoint) = &container.entrypoint {
+        println!("{}{}", field("Entrypoint:"), serde_json::to_string(entrypoint)?);
+    }
+    if let Some(cmd) = &container.cmd {
+        println!("{}{}", field("Cmd:"), serde_json::to_string(cmd)?);
+    }
+    if let Some(ports) = container.exposed_ports.filter(|ports| !ports.is_empty()) {
+        println!("{}{}", field("Ports:"), ports.into_keys().collect::<Vec<_>>().join(", "));
+    }
+    if let Some(env) = container.env.filter(|env| !env.is_empty()) {
+        println!("{}", field("Env:"));
+        for var in env {
+            println!("  {var}");
+        }
+    }
+    if let Some(labels) = container.labels.filter(|labels| !labels.is_empty()) {
+        println!("{}", field("Labels:"));
+        for (key, value) in labels {
+            println!("  {key}={value}");
+        }
+    }
+    println!("{}{} in {} layers", field("Size:"), human_size(info.total_size()), info.layers.len());
+    for layer in &info.layers {
+        println!("  {:>10}  {}", human_size(layer.size), layer.digest);
+    }
+    Ok(())
+}
+
+/// 输出每个标签的比较结果，sync 时以配置中的 pusher 重新同步过期的镜像
+async fn detect_drift(
+    settings: &Settings,
+    repositories: &[String],
+    platforms: &[Platform],
+    sync: bool,
+    quiet: bool,
+) -> anyhow::Result<()> {
+    if sync && platforms.len() > 1 {
+        anyhow::bail!("--sync pushes every platform to the same tag, pass at most one --platform");
+    }
+    // 只有同步历史中记录了源镜像的标签才能比较
+    let mut sources = HashMap::new();
+    if settings.provenance != ProvenanceMode::Off && settings.pusher_backend == Backend::Github {
+        let (octocrab, urls, owner, repo) = pusher_client(&settings.github_pusher_repo, settings)?;
+        let push_image = PushImage::from_client(octocrab, &owner, &repo).with_urls(&urls);
+        match push_image.history(&HistoryFilter::default()).await {
+            Ok(records) => sources = drift::history_sources(settings, &records),
+            Err(e) => println!("{} can not read sync history: {e}", style("warning:").yellow()),
+        }
+    }
+
+    let spinner = cliclack::spinner();
+    spinner.start(format!("comparing {} with upstream...", settings.namespace));
+    let entries = match drift::check(settings, &RegistryClient::new(), &sources, repositories, platforms).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            spinner.error("failed to compare images");
+            return Err(e);
+        }
+    };
+    spinner.stop(format!("checked {} tags", entries.len()));
+
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for entry in &entries {
+        *counts.entry(entry.status.to_string()).or_default() += 1;
+        if quiet && entry.status == DriftStatus::UpToDate {
+            continue;
+        }
+        let status = match &entry.status {
+            DriftStatus::UpToDate => style(entry.status.to_string()).green(),
+            DriftStatus::Stale => style(entry.status.to_string()).yellow(),
+            DriftStatus::MissingUpstream | DriftStatus::Failed(_) => style(entry.status.to_string()).red(),
+            DriftStatus::MirrorOnly => style(entry.status.to_string()).dim(),
+        };
+        let upstream = entry.upstream.as_ref().map(|u| format!(" <= {u}")).unwrap_or_default();
+        let name = match entry.tag.as_str() {
+            "" => entry.repository.clone(),
+            tag => format!("{}:{tag}", entry.repository),
+        };
+        println!("{status:<16} {name}{upstream}");
+        if let DriftStatus::Failed(e) = &entry.status {
+            println!("    {}", style(e).dim());
+        }
+    }
+    println!(
+        "{}",
+        counts
+            .iter()
+            .map(|(status, count)| format!("{count} {status}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let stale = entries
+        .iter()
+        .filter(|e| e.status == DriftStatus::Stale)
+        .filter_map(|e| e.mapping(settings.naming_strategy, settings.naming_separator()))
+        .collect::<Vec<_>>();
+    if !sync || stale.is_empty() {
+        return Ok(());
+    }
+    let records = if settings.provenance == ProvenanceMode::Off {
+        Vec::new()
+    } else {
+        mirror_records(settings, &stale, platforms).await
+    };
+    let push_image = build_pusher(
+        settings.pusher_backend,
+        &settings.github_pusher_repo,
+        settings,
+        &settings.pusher_workflow,
+        &settings.pusher_workflow_input,
+        &settings.pusher_git_url,
+        Provenance {
+            mode: settings.provenance,
+            records,
+        },
+    )?;
+    let lines = stale
+        .iter()
+        .flat_map(|m| image_lines(&m.line(settings.naming_strategy), platforms))
+        .collect::<Vec<_>>();
+    push_image.push(PushMode::Commit, &lines).await?;
+    for mapping in &stale {
+        println!("queued {} => {}", mapping.source, swr::target_reference(settings, mapping));
+    }
+    Ok(())
+}
+
+/// 本地 blob 缓存的 ls、prune 和 du
+fn manage_cache(command: &CacheCommands) -> anyhow::Result<()> {
+    let cache = BlobCache::open_default()?;
+    match command {
+        CacheCommands::Ls => {
+            for entry in cache.entries()? {
+                let modified: DateTime<Local> = entry.modified.into();
+                println!(
+                    "{} {:>10} {}{}",
+                    style(modified.format("%Y-%m-%d %H:%M:%S")).dim(),
+                    human_size(entry.size),
+                    entry.digest,
+                    if entry.partial { " (partial)" } else { "" }
+                );
+            }
+        }
+        CacheCommands::Prune { older_than } => {
+            let older_than = older_than.map(|days| Duration::from_secs(days * 24 * 60 * 60));
+            let (count, bytes) = cache.prune(older_than)?;
+            println!("removed {count} blobs, freed {}", human_size(bytes));
+        }
+        CacheCommands::Du => {
+            let (count, bytes) = cache.usage()?;
+            println!("{} in {count} blobs ({})", human_size(bytes), cache.root().display());
+        }
+    }
+    Ok(())
+}
+
+/// 配置了 github app 时以 app 的 installation 身份访问，否则使用 personal token
+fn github_octocrab(urls: &GithubUrls, settings: &Settings) -> anyhow::Result<Octocrab> {
+    match settings.github_app()? {
+        Some(app) => app.client(urls),
+        None => github_client(urls, &settings.github_token),
+    }
+}
+
+/// 仓库所在 github 的地址，地址中带有端口或为 http 时以地址为准，否则使用主机对应的配置
+fn location_urls(location: &RepoLocation, settings: &Settings) -> GithubUrls {
+    let host = location.host_or(settings.github_host());
+    match location.github_urls() {
+        Some(urls) if urls != GithubUrls::from_host(host) => urls,
+        _ => settings.github_urls(host),
+    }
+}
+
+/// pusher 仓库的 github 客户端、地址和 owner/repo
+fn pusher_client(pusher: &str, settings: &Settings) -> anyhow::Result<(Octocrab, GithubUrls, String, String)> {
+    let (urls, owner, repo) = parse_pusher_args(pusher, settings)?;
+    Ok((github_octocrab(&urls, settings)?, urls, owner, repo))
+}
 
-    anyhow::bail!("pusher is not valid")
+/// 支持 owner/repo，以及任意主机上的 https、ssh:// 和 git@host:owner/repo.git 地址
+/// 地址中没有主机时使用配置中的 github_host
+fn parse_pusher_args(pusher: &str, settings: &Settings) -> anyhow::Result<(GithubUrls, String, String)> {
+    let location = RepoLocation::parse(pusher)?;
+    let urls = location_urls(&location, settings);
+    Ok((urls, location.owner, location.repo))
 }
@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::image::{split_reference, ImageReference, DOCKER_HUB};
+
+/// 源镜像和目标镜像的分隔符，如 `ghcr.io/foo/bar:1.0=>tools/bar:1.0`
+pub const MAPPING_SEPARATOR: &str = "=>";
+
+/// 未配置时路径各段之间的连接符
+pub const DEFAULT_SEPARATOR: &str = "_";
+
+/// 源镜像在 SWR 命名空间中的命名方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum NamingStrategy {
+    /// 只保留最后一段，`bitnami/nginx` => `nginx`
+    #[default]
+    Flatten,
+    /// 保留完整路径并用分隔符连接，`bitnami/nginx` => `bitnami_nginx`
+    KeepPath,
+    /// 以 registry 为前缀并保留完整路径，`ghcr.io/foo/bar` => `ghcr.io_foo_bar`
+    RegistryPrefix,
+}
+
+/// 一条同步映射，target 为命名空间下的 `仓库名:标签`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageMapping {
+    pub source: String,
+    /// 解析后的源镜像地址
+    pub reference: ImageReference,
+    pub target: String,
+    /// target 是否由 `source=>target` 显式指定
+    pub explicit: bool,
+}
+
+impl ImageMapping {
+    /// 解析 `source` 或 `source=>target`，未指定 target 时按命名方式生成
+    pub fn parse(spec: &str, strategy: NamingStrategy, separator: &str) -> anyhow::Result<Self> {
+        let (source, target) = match spec.split_once(MAPPING_SEPARATOR) {
+            Some((source, target)) => (source.trim(), Some(target.trim())),
+            None => (spec.trim(), None),
+        };
+        if source.is_empty() {
+            anyhow::bail!("source image is empty in `{spec}`");
+        }
+        let reference: ImageReference = source.parse()?;
+        // 只有 digest 的源镜像没有可沿用的标签，映射为 latest 会覆盖目标中真正的 latest
+        let pinned = reference.tag.is_none() && reference.digest.is_some();
+
+        match target {
+            Some(target) => {
+                let (name, tag, digest) = split_reference(target)?;
+                if name.is_empty() {
+                    anyhow::bail!("target image is empty in `{spec}`");
+                }
+                if digest.is_some() {
+                    anyhow::bail!("target image `{target}` can not have a digest in `{spec}`");
+                }
+                // 目标未写标签时沿用源镜像的标签
+                let target = match tag {
+                    Some(tag) => format!("{name}:{tag}"),
+                    None if pinned => {
+                        anyhow::bail!("source image `{source}` has only a digest, add a tag to the target like `{source}{MAPPING_SEPARATOR}{name}:<tag>`")
+                    }
+                    None => format!("{name}:{}", reference.tag_or_latest()),
+                };
+                Ok(Self {
+                    source: source.to_string(),
+                    reference,
+                    target,
+                    explicit: true,
+                })
+            }
+            None if pinned => {
+                anyhow::bail!("source image `{source}` has only a digest, specify a target tag like `{source}{MAPPING_SEPARATOR}name:<tag>`")
+            }
+            None => Ok(Self {
+                source: source.to_string(),
+                target: target_name(&reference, strategy, separator),
+                reference,
+                explicit: false,
+            }),
+        }
+    }
+
+    /// SWR 中的仓库名和标签
+    pub fn target_repository(&self) -> (String, String) {
+        match split_reference(&self.target) {
+            Ok((name, tag, _)) => (name.to_string(), tag.unwrap_or_else(|| "latest".to_string())),
+            Err(_) => (self.target.clone(), "latest".to_string()),
+        }
+    }
+
+    /// 写入 images.txt 的镜像部分，使用规范化后的源镜像地址
+    /// pusher 默认的 flatten 命名不需要写出 target，带 digest 时 pusher 无法推断标签，总是写出 target
+    pub fn line(&self, strategy: NamingStrategy) -> String {
+        if !self.explicit && strategy == NamingStrategy::Flatten && self.reference.digest.is_none() {
+            self.reference.to_string()
+        } else {
+            format!("{}{MAPPING_SEPARATOR}{}", self.reference, self.target)
+        }
+    }
+}
+
+/// 按命名方式生成目标的 `仓库名:标签`
+pub fn target_name(source: &ImageReference, strategy: NamingStrategy, separator: &str) -> String {
+    // 只有 docker hub 的官方镜像补全了 library/，其他 registry 中的 library 是真实的路径
+    let path = match source.repository.strip_prefix("library/") {
+        Some(path) if source.registry == DOCKER_HUB => path,
+        _ => source.repository.as_str(),
+    };
+    let path = path.replace('/', separator);
+    let name = match strategy {
+        NamingStrategy::Flatten => source.short_name().to_string(),
+        NamingStrategy::KeepPath => path,
+        NamingStrategy::RegistryPrefix => {
+            format!("{}{separator}{path}", source.registry.replace(':', separator))
+        }
+    };
+    format!("{name}:{}", source.tag_or_latest())
+}
+
+/// 找出映射到同一个目标的源镜像
+pub fn collisions(mappings: &[ImageMapping]) -> Vec<(String, Vec<String>)> {
+    let mut targets: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for mapping in mappings {
+        let sources = targets.entry(mapping.target.as_str()).or_default();
+        if !sources.contains(&mapping.source) {
+            sources.push(mapping.source.clone());
+        }
+    }
+    targets
+        .into_iter()
+        .filter(|(_, sources)| sources.len() > 1)
+        .map(|(target, sources)| (target.to_string(), sources))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_target_name() {
+        let sep = DEFAULT_SEPARATOR;
+        let name = |source: &str, strategy| target_name(&source.parse().unwrap(), strategy, sep);
+        assert_eq!(name("bitnami/nginx:1.27", NamingStrategy::Flatten), "nginx:1.27");
+        assert_eq!(name("bitnami/nginx:1.27", NamingStrategy::KeepPath), "bitnami_nginx:1.27");
+        assert_eq!(name("nginx", NamingStrategy::KeepPath), "nginx:latest");
+        assert_eq!(name("localhost:5000/a/b:1", NamingStrategy::RegistryPrefix), "localhost_5000_a_b:1");
+        // 不同路径下的同名镜像不会冲突
+        assert_eq!(name("ghcr.io/a/x:1", NamingStrategy::RegistryPrefix), "ghcr.io_a_x:1");
+        assert_eq!(name("ghcr.io/b/x:1", NamingStrategy::RegistryPrefix), "ghcr.io_b_x:1");
+        assert_eq!(name("nginx:1", NamingStrategy::RegistryPrefix), "docker.io_nginx:1");
+        // 其他 registry 中的 library 不能去掉，否则与 ghcr.io/foo 冲突
+        assert_eq!(name("ghcr.io/library/foo:1", NamingStrategy::KeepPath), "library_foo:1");
+        assert_eq!(name("ghcr.io/library/foo:1", NamingStrategy::RegistryPrefix), "ghcr.io_library_foo:1");
+        assert_eq!(name("ghcr.io/foo:1", NamingStrategy::RegistryPrefix), "ghcr.io_foo:1");
+    }
+
+    #[test]
+    fn test_parse_mapping() {
+        let m = ImageMapping::parse("ghcr.io/foo/bar:1.0=>tools/bar:1.0", NamingStrategy::Flatten, "_").unwrap();
+        assert_eq!(m.source, "ghcr.io/foo/bar:1.0");
+        assert_eq!(m.target_repository(), ("tools/bar".into(), "1.0".into()));
+        assert_eq!(m.line(NamingStrategy::Flatten), "ghcr.io/foo/bar:1.0=>tools/bar:1.0");
+
+        let m = ImageMapping::parse("ghcr.io/foo/bar:1.0=>bar", NamingStrategy::Flatten, "_").unwrap();
+        assert_eq!(m.target, "bar:1.0");
+
+        let m = ImageMapping::parse("nginx:1.27", NamingStrategy::Flatten, "_").unwrap();
+        assert_eq!(m.line(NamingStrategy::Flatten), "docker.io/library/nginx:1.27");
+
+        // 目标中的端口不是标签
+        let m = ImageMapping::parse("nginx:1.27=>host:5000/nginx", NamingStrategy::Flatten, "_").unwrap();
+        assert_eq!(m.target_repository(), ("host:5000/nginx".into(), "1.27".into()));
+
+        // 只有 digest 的源镜像需要指定目标标签
+        let digest = format!("sha256:{}", "a".repeat(64));
+        assert!(ImageMapping::parse(&format!("nginx@{digest}"), NamingStrategy::Flatten, "_").is_err());
+        assert!(ImageMapping::parse(&format!("nginx@{digest}=>nginx"), NamingStrategy::Flatten, "_").is_err());
+        let m = ImageMapping::parse(&format!("nginx@{digest}=>nginx:pinned"), NamingStrategy::Flatten, "_").unwrap();
+        assert_eq!(m.target_repository(), ("nginx".into(), "pinned".into()));
+        assert_eq!(m.line(NamingStrategy::Flatten), format!("docker.io/library/nginx@{digest}=>nginx:pinned"));
+        assert!(ImageMapping::parse(&format!("nginx:1=>nginx@{digest}"), NamingStrategy::Flatten, "_").is_err());
+
+        assert!(ImageMapping::parse("=>bar", NamingStrategy::Flatten, "_").is_err());
+        assert!(ImageMapping::parse("Nginx:1.27", NamingStrategy::Flatten, "_").is_err());
+    }
+
+    #[test]
+    fn test_collisions() {
+        let mappings = ["bitnami/nginx", "library/nginx", "redis"]
+            .map(|s| ImageMapping::parse(s, NamingStrategy::Flatten, "_").unwrap());
+        let found = collisions(&mappings);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, "nginx:latest");
+        assert_eq!(found[0].1.len(), 2);
+    }
+}
@@ -0,0 +1,124 @@
+use std::{fmt, str::FromStr};
+
+/// 镜像支持的操作系统，取自 OCI image index 规范中的 GOOS 取值
+const KNOWN_OS: [&str; 15] = [
+    "aix", "android", "darwin", "dragonfly", "freebsd", "illumos", "ios", "js", "linux", "netbsd",
+    "openbsd", "plan9", "solaris", "wasip1", "windows",
+];
+
+/// 镜像支持的架构及其变体，取自 OCI image index 规范中的 GOARCH 取值
+const KNOWN_ARCH: [(&str, &[&str]); 14] = [
+    ("386", &[]),
+    ("amd64", &["v1", "v2", "v3", "v4"]),
+    ("arm", &["v5", "v6", "v7", "v8"]),
+    ("arm64", &["v8", "v9"]),
+    ("loong64", &[]),
+    ("mips", &[]),
+    ("mipsle", &[]),
+    ("mips64", &[]),
+    ("mips64le", &[]),
+    ("ppc64", &[]),
+    ("ppc64le", &[]),
+    ("riscv64", &[]),
+    ("s390x", &[]),
+    ("wasm", &[]),
+];
+
+/// 镜像平台，格式为 `os/arch[/variant]`，如 `linux/arm64`、`linux/arm/v7`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Platform {
+    pub os: String,
+    pub architecture: String,
+    pub variant: Option<String>,
+}
+
+impl Platform {
+    /// 是否与 manifest index 中的平台匹配，未指定 variant 时匹配所有 variant
+    pub fn matches(&self, os: &str, architecture: &str, variant: Option<&str>) -> bool {
+        self.os == os
+            && self.architecture == architecture
+            && (self.variant.is_none() || self.variant.as_deref() == variant)
+    }
+}
+
+impl FromStr for Platform {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.split('/').collect::<Vec<_>>();
+        if parts.len() < 2 || parts.len() > 3 {
+            anyhow::bail!("invalid platform `{s}`, expected os/arch[/variant] like linux/arm64");
+        }
+
+        let os = parts[0].to_lowercase();
+        if !KNOWN_OS.contains(&os.as_str()) {
+            anyhow::bail!("unknown os `{os}` in platform `{s}`, expected one of: {}", KNOWN_OS.join(", "));
+        }
+
+        let architecture = parts[1].to_lowercase();
+        let Some((_, variants)) = KNOWN_ARCH.iter().find(|(arch, _)| *arch == architecture) else {
+            let known = KNOWN_ARCH.iter().map(|(arch, _)| *arch).collect::<Vec<_>>();
+            anyhow::bail!(
+                "unknown architecture `{architecture}` in platform `{s}`, expected one of: {}",
+                known.join(", ")
+            );
+        };
+
+        let variant = parts.get(2).map(|v| v.to_lowercase());
+        if let Some(variant) = &variant {
+            if !variants.contains(&variant.as_str()) {
+                if variants.is_empty() {
+                    anyhow::bail!("architecture `{architecture}` has no variants, got `{variant}`");
+                }
+                anyhow::bail!(
+                    "unknown variant `{variant}` for `{architecture}`, expected one of: {}",
+                    variants.join(", ")
+                );
+            }
+        }
+
+        Ok(Self {
+            os,
+            architecture,
+            variant,
+        })
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        if let Some(variant) = &self.variant {
+            write!(f, "/{variant}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_platform() {
+        let p: Platform = "linux/arm64".parse().unwrap();
+        assert_eq!(p.os, "linux");
+        assert_eq!(p.architecture, "arm64");
+        assert_eq!(p.variant, None);
+        assert_eq!("linux/arm/v7".parse::<Platform>().unwrap().to_string(), "linux/arm/v7");
+
+        assert!("linux".parse::<Platform>().is_err());
+        assert!("linux/arm64/v7".parse::<Platform>().is_err());
+        assert!("linux/x86".parse::<Platform>().is_err());
+        assert!("beos/amd64".parse::<Platform>().is_err());
+        assert!("linux/s390x/v1".parse::<Platform>().is_err());
+    }
+
+    #[test]
+    fn test_platform_matches() {
+        let p: Platform = "linux/arm".parse().unwrap();
+        assert!(p.matches("linux", "arm", Some("v7")));
+        let p: Platform = "linux/arm/v6".parse().unwrap();
+        assert!(!p.matches("linux", "arm", Some("v7")));
+    }
+}
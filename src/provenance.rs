@@ -0,0 +1,178 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+/// pusher 仓库中记录同步历史的文件
+pub const LOG_FILE: &str = "mirror-log.jsonl";
+/// 提交信息中记录同步历史的 trailer 名称
+pub const TRAILER: &str = "Mirror-Record";
+
+/// 同步记录的保存方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProvenanceMode {
+    /// 不记录
+    #[default]
+    Off,
+    /// 追加到 pusher 仓库的 mirror-log.jsonl
+    Log,
+    /// 写入 images.txt 提交信息的 trailer
+    Trailer,
+}
+
+/// 一次同步的记录：谁在什么时候把哪个 digest 的源镜像同步到了哪里
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MirrorRecord {
+    pub requester: String,
+    pub source: String,
+    /// 同步时解析到的源镜像 digest，无法访问源 registry 时为空
+    pub digest: Option<String>,
+    pub target: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl MirrorRecord {
+    pub fn new(source: &str, digest: Option<String>, target: &str) -> Self {
+        Self {
+            requester: String::new(),
+            source: source.to_string(),
+            digest,
+            target: target.to_string(),
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+/// 把记录写成提交信息的 trailer，每条记录一行
+pub fn trailers(records: &[MirrorRecord]) -> anyhow::Result<String> {
+    let lines = records
+        .iter()
+        .map(|r| Ok(format!("{TRAILER}: {}", serde_json::to_string(r)?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(lines.join("\n"))
+}
+
+/// 从提交信息中解析记录，忽略无法解析的行
+pub fn parse_trailers(message: &str) -> Vec<MirrorRecord> {
+    message
+        .lines()
+        .filter_map(|line| line.strip_prefix(TRAILER)?.strip_prefix(':'))
+        .filter_map(|json| serde_json::from_str(json.trim()).ok())
+        .collect()
+}
+
+/// 把记录追加到 mirror-log.jsonl 的现有内容后
+pub fn append_log(current: &str, records: &[MirrorRecord]) -> anyhow::Result<String> {
+    let mut content = current.trim_end().to_string();
+    for record in records {
+        if !content.is_empty() {
+            content.push('\n');
+        }
+        content.push_str(&serde_json::to_string(record)?);
+    }
+    content.push('\n');
+    Ok(content)
+}
+
+/// 解析 mirror-log.jsonl，忽略空行和无法解析的行
+pub fn parse_log(content: &str) -> Vec<MirrorRecord> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                tracing::debug!("skip invalid record `{line}`: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// history 命令的过滤条件，未设置的条件不过滤
+#[derive(Debug, Default)]
+pub struct HistoryFilter {
+    /// 源镜像或目标镜像包含的字符串
+    pub image: Option<String>,
+    pub requester: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+}
+
+impl HistoryFilter {
+    pub fn matches(&self, record: &MirrorRecord) -> bool {
+        let image = self
+            .image
+            .as_deref()
+            .is_none_or(|image| record.source.contains(image) || record.target.contains(image));
+        let requester = self
+            .requester
+            .as_deref()
+            .is_none_or(|requester| record.requester == requester);
+        let since = self.since.is_none_or(|since| record.timestamp >= since);
+        image && requester && since
+    }
+}
+
+/// 解析 `2024-12-01` 或 RFC 3339 格式的时间
+pub fn parse_since(input: &str) -> anyhow::Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc());
+    }
+    DateTime::parse_from_rfc3339(input)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|_| anyhow::anyhow!("invalid time `{input}`, expected 2024-12-01 or 2024-12-01T08:00:00Z"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(source: &str, requester: &str, timestamp: &str) -> MirrorRecord {
+        MirrorRecord {
+            requester: requester.to_string(),
+            source: source.to_string(),
+            digest: Some("sha256:abc".into()),
+            target: format!("swr.cn-south-1.myhuaweicloud.com/ns/{source}"),
+            timestamp: parse_since(timestamp).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_trailers_roundtrip() {
+        let records = vec![
+            record("nginx:1.27", "alice", "2024-12-01T08:00:00Z"),
+            record("redis:7", "alice", "2024-12-01T08:00:00Z"),
+        ];
+        let message = format!("sync nginx:1.27, redis:7\n\n{}", trailers(&records).unwrap());
+        assert_eq!(parse_trailers(&message), records);
+    }
+
+    #[test]
+    fn test_append_log() {
+        let first = record("nginx:1.27", "alice", "2024-12-01");
+        let second = record("redis:7", "bob", "2024-12-02");
+        let content = append_log("", std::slice::from_ref(&first)).unwrap();
+        let content = append_log(&content, std::slice::from_ref(&second)).unwrap();
+        assert_eq!(content.lines().count(), 2);
+        assert_eq!(parse_log(&format!("{content}\nnot json\n")), vec![first, second]);
+    }
+
+    #[test]
+    fn test_history_filter() {
+        let r = record("nginx:1.27", "alice", "2024-12-02T08:00:00Z");
+        assert!(HistoryFilter::default().matches(&r));
+
+        let filter = HistoryFilter {
+            image: Some("nginx".into()),
+            requester: Some("alice".into()),
+            since: Some(parse_since("2024-12-01").unwrap()),
+        };
+        assert!(filter.matches(&r));
+
+        let filter = HistoryFilter {
+            since: Some(parse_since("2024-12-03").unwrap()),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&r));
+        assert!(parse_since("yesterday").is_err());
+    }
+}
@@ -0,0 +1,525 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use reqwest::{header, RequestBuilder};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    image::PushMode,
+    workflow::{self, RunFailure, Trigger},
+};
+
+use super::{
+    check, error_message, is_sha_conflict, update_image_lines, Account, Pusher, PusherConfig, PusherRepo,
+    DEFAULT_WORKFLOW, DEFAULT_WORKFLOW_INPUT,
+};
+
+/// 通过 Gitea/Forgejo 的 api 触发 pusher 仓库中的 Actions
+pub struct GiteaPusher {
+    http_client: reqwest::Client,
+    base_url: String,
+    token: String,
+    owner: String,
+    repo: String,
+    config: PusherConfig,
+    workflow: Option<String>,
+    workflow_input: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContentsResponse {
+    sha: String,
+    #[serde(default)]
+    content: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FileResponse {
+    commit: FileCommit,
+}
+
+#[derive(Debug, Deserialize)]
+struct FileCommit {
+    sha: String,
+}
+
+/// Actions 的 task 列表，每个 job 对应一个 task
+#[derive(Debug, Deserialize)]
+struct TaskList {
+    #[serde(default)]
+    workflow_runs: Vec<Task>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct Task {
+    name: String,
+    head_sha: String,
+    run_number: u64,
+    event: String,
+    status: String,
+    /// workflow 文件名，如 docker.yaml
+    workflow_id: String,
+    /// run 的网页地址
+    url: String,
+    created_at: DateTime<Utc>,
+}
+
+/// task 结束时的状态
+const FINISHED_STATUSES: [&str; 4] = ["success", "failure", "cancelled", "skipped"];
+
+#[derive(Debug, Deserialize)]
+struct BranchResponse {
+    commit: BranchCommit,
+}
+
+#[derive(Debug, Deserialize)]
+struct BranchCommit {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    login: String,
+    #[serde(default)]
+    email: String,
+}
+
+impl GiteaPusher {
+    /// base_url 为实例的网页地址，如 `https://git.example.com`
+    pub fn new(base_url: &str, token: &str, owner: &str, repo: &str) -> Self {
+        Self {
+            http_client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            config: PusherConfig::default(),
+            workflow: None,
+            workflow_input: None,
+        }
+    }
+
+    /// 设置 workflow_dispatch 使用的 workflow 文件名和 input 名称，空字符串表示使用默认值
+    pub fn with_workflow(mut self, workflow: &str, input: &str) -> Self {
+        self.workflow = Some(workflow.to_string()).filter(|v| !v.is_empty());
+        self.workflow_input = Some(input.to_string()).filter(|v| !v.is_empty());
+        self
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> RequestBuilder {
+        self.http_client
+            .request(method, format!("{}/api/v1{path}", self.base_url))
+            .header(header::AUTHORIZATION, format!("token {}", self.token))
+            .header(header::ACCEPT, "application/json")
+    }
+
+    fn contents_route(&self, path: &str) -> String {
+        format!("/repos/{}/{}/contents/{path}", self.owner, self.repo)
+    }
+
+    /// 读取 images.txt 的内容和 blob sha
+    async fn read_image_file(&self, path: &str, branch: &str) -> anyhow::Result<(String, String)> {
+        let current: ContentsResponse = check(
+            self.request(reqwest::Method::GET, &self.contents_route(path))
+                .query(&[("ref", branch)])
+                .send()
+                .await?,
+        )
+        .await?
+        .json()
+        .await?;
+        Ok((decode_content(current.content.as_deref())?, current.sha))
+    }
+
+    /// 按读取时的 blob sha 更新 images.txt，sha 过期时返回 `Err(错误信息)`
+    async fn write_image_file(
+        &self,
+        path: &str,
+        body: &Value,
+        content: String,
+        sha: String,
+    ) -> anyhow::Result<Result<String, String>> {
+        let mut body = body.clone();
+        body["content"] = json!(STANDARD.encode(&content));
+        body["sha"] = json!(sha);
+        let response = self.request(reqwest::Method::PUT, &self.contents_route(path)).json(&body).send().await?;
+
+        let status = response.status();
+        if status.is_success() {
+            let update: FileResponse = response.json().await?;
+            return Ok(Ok(update.commit.sha));
+        }
+        let message = error_message(response).await;
+        if !is_sha_conflict(status, &message) {
+            anyhow::bail!("update {path} failed ({status}): {message}");
+        }
+        Ok(Err(message))
+    }
+
+    /// 更新 images.txt，返回产生的 commit sha
+    pub async fn update_image_file(
+        &self,
+        images: &[String],
+        git_user_name: Option<String>,
+        git_user_email: Option<String>,
+    ) -> anyhow::Result<String> {
+        let path = self.config.path();
+        let branch = self.config.branch();
+        let identity = self.commit_identity(git_user_name, git_user_email).await?;
+
+        let mut body = json!({
+            "message": format!("sync {}", images.join(", ")),
+            "branch": branch,
+        });
+        if let Some((name, email)) = identity {
+            let identity = json!({ "name": name, "email": email });
+            body["author"] = identity.clone();
+            body["committer"] = identity;
+        }
+        update_image_lines(
+            &path,
+            images,
+            || self.read_image_file(&path, &branch),
+            |content, sha| self.write_image_file(&path, &body, content, sha),
+        )
+        .await
+    }
+
+    /// 调用 Actions 的 dispatch 接口触发 workflow，镜像列表作为 input 传入，不产生提交
+    pub async fn dispatch_workflow(&self, images: &[String]) -> anyhow::Result<Trigger> {
+        let branch = self.config.branch();
+        let workflow = self.workflow.clone().map_or(DEFAULT_WORKFLOW.into(), |v| v);
+        let input = self.workflow_input.clone().map_or(DEFAULT_WORKFLOW_INPUT.into(), |v| v);
+        let mut inputs = Map::new();
+        inputs.insert(input, Value::String(images.join("\n")));
+
+        // 记录调用时分支的提交，用于找到这次触发的 run
+        let route = format!("/repos/{}/{}/branches/{branch}", self.owner, self.repo);
+        let head: BranchResponse = check(self.request(reqwest::Method::GET, &route).send().await?)
+            .await?
+            .json()
+            .await?;
+
+        let since = chrono::Utc::now();
+        let route = format!("/repos/{}/{}/actions/workflows/{workflow}/dispatches", self.owner, self.repo);
+        check(
+            self.request(reqwest::Method::POST, &route)
+                .json(&json!({ "ref": branch, "inputs": inputs }))
+                .send()
+                .await?,
+        )
+        .await?;
+        Ok(Trigger::Dispatch {
+            workflow,
+            branch,
+            head_sha: head.commit.id,
+            // gitea 的 run 列表不返回触发的账户
+            actor: None,
+            since,
+        })
+    }
+
+    async fn list_tasks(&self) -> anyhow::Result<Vec<Task>> {
+        let route = format!("/repos/{}/{}/actions/tasks", self.owner, self.repo);
+        let tasks: TaskList = check(
+            self.request(reqwest::Method::GET, &route)
+                .query(&[("limit", "50")])
+                .send()
+                .await?,
+        )
+        .await?
+        .json()
+        .await?;
+        Ok(tasks.workflow_runs)
+    }
+}
+
+#[async_trait]
+impl PusherRepo for GiteaPusher {
+    fn config(&self) -> &PusherConfig {
+        &self.config
+    }
+
+    fn config_mut(&mut self) -> &mut PusherConfig {
+        &mut self.config
+    }
+
+    fn clone_url(&self) -> String {
+        format!("{}/{}/{}.git", self.base_url, self.owner, self.repo)
+    }
+
+    /// token 对应的用户，没有公开邮箱时 email 为空
+    async fn account(&self) -> anyhow::Result<Option<Account>> {
+        let user: User = check(self.request(reqwest::Method::GET, "/user").send().await?)
+            .await?
+            .json()
+            .await?;
+        Ok(Some(Account {
+            name: user.login,
+            email: Some(user.email).filter(|v| !v.is_empty()),
+        }))
+    }
+}
+
+#[async_trait]
+impl Pusher for GiteaPusher {
+    async fn push(&self, mode: PushMode, images: &[String]) -> anyhow::Result<Trigger> {
+        match mode {
+            PushMode::Commit => {
+                let sha = self.update_image_file(images, None, None).await?;
+                Ok(Trigger::Commit { sha })
+            }
+            PushMode::Dispatch => self.dispatch_workflow(images).await,
+            PushMode::Git => {
+                let sha = self.push_git(images).await?;
+                Ok(Trigger::Commit { sha })
+            }
+        }
+    }
+
+    /// 轮询 Actions 的 task 列表，run 的所有 job 结束后返回，失败时返回失败的 job
+    async fn wait_for_run(
+        &self,
+        trigger: &Trigger,
+        timeout: Duration,
+    ) -> anyhow::Result<Result<(), RunFailure>> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let spinner = cliclack::spinner();
+        spinner.start("waiting for workflow run to start...");
+
+        let mut run_number = None;
+        loop {
+            if tokio::time::Instant::now() >= deadline {
+                spinner.error("timed out waiting for workflow run");
+                anyhow::bail!("timed out after {}s waiting for workflow run", timeout.as_secs());
+            }
+
+            let tasks = self.list_tasks().await?;
+            let run = run_number.or_else(|| find_run(&tasks, trigger));
+            let jobs = tasks
+                .into_iter()
+                .filter(|task| Some(task.run_number) == run)
+                .collect::<Vec<_>>();
+
+            if let Some(first) = jobs.first() {
+                run_number = Some(first.run_number);
+                if jobs.iter().all(|job| FINISHED_STATUSES.contains(&job.status.as_str())) {
+                    let failed = jobs.iter().find(|job| matches!(job.status.as_str(), "failure" | "cancelled"));
+                    let Some(failed) = failed else {
+                        spinner.stop(format!("workflow run succeeded: {}", first.url));
+                        return Ok(Ok(()));
+                    };
+                    spinner.error(format!("workflow run {}: {}", failed.status, failed.url));
+                    // gitea 的 api 不提供 job 的日志，只返回失败的 job
+                    return Ok(Err(RunFailure {
+                        url: failed.url.clone(),
+                        job: Some(failed.name.clone()),
+                        step: None,
+                        log_tail: Vec::new(),
+                    }));
+                }
+                spinner.set_message(format!("workflow run #{} is running...", first.run_number));
+            }
+
+            tokio::time::sleep(workflow::POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// 查找触发对应的 run，返回 run 的编号，还没开始时返回 None
+fn find_run(tasks: &[Task], trigger: &Trigger) -> Option<u64> {
+    let matched = tasks.iter().filter(|task| match trigger {
+        Trigger::Commit { sha } => task.event == "push" && &task.head_sha == sha,
+        Trigger::Dispatch {
+            workflow,
+            head_sha,
+            since,
+            ..
+        } => {
+            task.event == "workflow_dispatch"
+                && &task.workflow_id == workflow
+                && &task.head_sha == head_sha
+                && task.created_at >= *since - chrono::Duration::seconds(5)
+        }
+        Trigger::Pipeline { .. } => false,
+    });
+    // 取触发之后最早创建的 run
+    matched.min_by_key(|task| task.created_at).map(|task| task.run_number)
+}
+
+/// contents 接口返回的 base64 内容可能带有换行
+fn decode_content(content: Option<&str>) -> anyhow::Result<String> {
+    let content = content
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+    Ok(String::from_utf8(STANDARD.decode(content)?)?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{
+        extract::{Path, State},
+        http::{HeaderMap, StatusCode},
+        routing::{get, post},
+        Json, Router,
+    };
+
+    use super::*;
+
+    /// 模拟 gitea contents 和 dispatch 接口，sha 过期时返回 422
+    #[derive(Default)]
+    struct Repo {
+        sha: String,
+        content: String,
+        dispatched: Option<(String, Value)>,
+        tokens: Vec<String>,
+    }
+
+    type Shared = Arc<Mutex<Repo>>;
+
+    fn record_token(state: &Shared, headers: &HeaderMap) {
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        state.lock().unwrap().tokens.push(token.to_string());
+    }
+
+    async fn mock_gitea(state: Shared) -> String {
+        let app = Router::new()
+            .route(
+                "/api/v1/repos/owner/repo/contents/images.txt",
+                get(|State(state): State<Shared>, headers: HeaderMap| async move {
+                    record_token(&state, &headers);
+                    let c = state.lock().unwrap();
+                    // 返回的 base64 可能带有换行
+                    Json(json!({ "sha": c.sha, "content": format!("{}\n", STANDARD.encode(&c.content)) }))
+                })
+                .put(|State(state): State<Shared>, headers: HeaderMap, Json(body): Json<Value>| async move {
+                    record_token(&state, &headers);
+                    let mut c = state.lock().unwrap();
+                    if body["sha"] != c.sha {
+                        let message = format!("sha does not match [given: {}, expected: {}]", body["sha"], c.sha);
+                        return (StatusCode::UNPROCESSABLE_ENTITY, Json(json!({ "message": message })));
+                    }
+                    let content = STANDARD.decode(body["content"].as_str().unwrap()).unwrap();
+                    c.content = String::from_utf8(content).unwrap();
+                    c.sha = "c".into();
+                    (StatusCode::OK, Json(json!({ "commit": { "sha": "commit1" } })))
+                }),
+            )
+            .route(
+                "/api/v1/user",
+                get(|| async { Json(json!({ "login": "alice", "email": "alice@example.com" })) }),
+            )
+            .route(
+                "/api/v1/repos/owner/repo/actions/tasks",
+                get(|| async {
+                    let task = |run: u64, name: &str, sha: &str, status: &str, created: &str| {
+                        json!({
+                            "id": run * 10, "name": name, "head_branch": "main", "head_sha": sha,
+                            "run_number": run, "event": "workflow_dispatch", "status": status,
+                            "workflow_id": "mirror.yaml", "url": format!("http://gitea/owner/repo/actions/runs/{run}"),
+                            "created_at": created,
+                        })
+                    };
+                    // run 2 是其他提交上同时触发的，run 1 早于本次触发
+                    Json(json!({
+                        "workflow_runs": [
+                            task(3, "verify", "head1", "failure", "2099-01-01T00:00:03Z"),
+                            task(3, "sync", "head1", "success", "2099-01-01T00:00:03Z"),
+                            task(2, "sync", "other", "running", "2099-01-01T00:00:02Z"),
+                            task(1, "sync", "head1", "success", "2000-01-01T00:00:00Z"),
+                        ],
+                        "total_count": 4,
+                    }))
+                }),
+            )
+            .route(
+                "/api/v1/repos/owner/repo/branches/main",
+                get(|| async { Json(json!({ "name": "main", "commit": { "id": "head1" } })) }),
+            )
+            .route(
+                "/api/v1/repos/owner/repo/actions/workflows/{workflow}/dispatches",
+                post(
+                    |State(state): State<Shared>,
+                     Path(workflow): Path<String>,
+                     Json(body): Json<Value>| async move {
+                        state.lock().unwrap().dispatched = Some((workflow, body));
+                        StatusCode::NO_CONTENT
+                    },
+                ),
+            )
+            .with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn test_write_image_file_conflict() {
+        let state = Arc::new(Mutex::new(Repo {
+            sha: "b".into(),
+            content: "redis:7".into(),
+            ..Default::default()
+        }));
+        let base = mock_gitea(state.clone()).await;
+        let pusher = GiteaPusher::new(&base, "secret", "owner", "repo");
+
+        let current = pusher.read_image_file("images.txt", "main").await.unwrap();
+        assert_eq!(current, ("redis:7".to_string(), "b".to_string()));
+
+        let body = json!({ "message": "sync nginx:1.27", "branch": "main" });
+        let conflict = pusher.write_image_file("images.txt", &body, "nginx:1.27".into(), "a".into()).await.unwrap();
+        assert!(conflict.is_err_and(|message| message.contains("sha does not match")));
+        let sha = pusher.write_image_file("images.txt", &body, "nginx:1.27".into(), "b".into()).await.unwrap();
+        assert_eq!(sha, Ok("commit1".to_string()));
+
+        let c = state.lock().unwrap();
+        assert_eq!(c.content, "nginx:1.27");
+        assert!(c.tokens.iter().all(|t| t == "token secret"));
+    }
+
+    #[tokio::test]
+    async fn test_account() {
+        let base = mock_gitea(Arc::new(Mutex::new(Repo::default()))).await;
+        let pusher = GiteaPusher::new(&base, "secret", "owner", "repo");
+
+        let account = pusher.account().await.unwrap();
+        assert_eq!(account, Some(Account { name: "alice".into(), email: Some("alice@example.com".into()) }));
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_workflow() {
+        let state = Arc::new(Mutex::new(Repo::default()));
+        let base = mock_gitea(state.clone()).await;
+        let pusher = GiteaPusher::new(&base, "secret", "owner", "repo").with_workflow("mirror.yaml", "");
+
+        let lines = ["nginx:1.27".to_string(), "redis:7".to_string()];
+        let trigger = pusher.push(PushMode::Dispatch, &lines).await.unwrap();
+        assert!(matches!(
+            trigger,
+            Trigger::Dispatch { ref workflow, ref head_sha, .. } if workflow == "mirror.yaml" && head_sha == "head1"
+        ));
+
+        let (workflow, body) = state.lock().unwrap().dispatched.clone().unwrap();
+        assert_eq!(workflow, "mirror.yaml");
+        assert_eq!(body, json!({ "ref": "main", "inputs": { "images": "nginx:1.27\nredis:7" } }));
+
+        let failure = pusher
+            .wait_for_run(&trigger, Duration::from_secs(10))
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(failure.url, "http://gitea/owner/repo/actions/runs/3");
+        assert_eq!(failure.job.as_deref(), Some("verify"));
+    }
+}
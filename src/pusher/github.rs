@@ -0,0 +1,567 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use octocrab::{
+    models::{repos::CommitAuthor, AppId, InstallationId},
+    repos::UpdateFileBuilder,
+    Octocrab,
+};
+use reqwest::StatusCode;
+use serde_json::{Map, Value};
+
+use crate::{
+    image::PushMode,
+    provenance::{self, HistoryFilter, MirrorRecord, ProvenanceMode},
+    remote::{self, GithubUrls},
+    workflow::{self, RunFailure, Trigger},
+};
+
+use super::{
+    is_sha_conflict, update_image_lines, Account, Pusher, PusherConfig, PusherRepo, DEFAULT_WORKFLOW,
+    DEFAULT_WORKFLOW_INPUT, MAX_UPDATE_ATTEMPTS,
+};
+
+pub struct PushImage{
+    octocrab: Octocrab,
+    repo: String,
+    owner: String,
+    config: PusherConfig,
+    workflow: Option<String>,
+    workflow_input: Option<String>,
+    urls: GithubUrls,
+    /// 是否以 github app 的身份认证，app 没有对应的 github 用户
+    app_auth: bool,
+    provenance: ProvenanceMode,
+    records: Vec<MirrorRecord>,
+}
+
+/// github app 的认证信息，私钥只保存路径
+#[derive(Debug, Clone)]
+pub struct GithubApp {
+    pub app_id: u64,
+    pub private_key_path: PathBuf,
+    pub installation_id: u64,
+}
+
+impl GithubApp {
+    /// 以 app 的 installation 身份创建 github 客户端，installation token 过期前由 octocrab 自动刷新
+    pub fn client(&self, urls: &GithubUrls) -> anyhow::Result<Octocrab> {
+        self.client_at(urls.api.clone())
+    }
+
+    fn client_at(&self, api_base: String) -> anyhow::Result<Octocrab> {
+        let pem = std::fs::read(&self.private_key_path)
+            .with_context(|| format!("Failed to read github app private key {}", self.private_key_path.display()))?;
+        let key = jsonwebtoken::EncodingKey::from_rsa_pem(&pem)
+            .with_context(|| format!("{} is not a valid RSA private key", self.private_key_path.display()))?;
+        let app = Octocrab::builder()
+            .base_uri(api_base)?
+            .app(AppId(self.app_id), key)
+            .build()?;
+        Ok(app.installation(InstallationId(self.installation_id))?)
+    }
+}
+
+/// 设置提交者，没有提交者时由 github 使用认证的账户
+fn with_identity<'octo, 'r>(
+    builder: UpdateFileBuilder<'octo, 'r>,
+    identity: &Option<(String, String)>,
+) -> UpdateFileBuilder<'octo, 'r> {
+    let Some((name, email)) = identity else {
+        return builder;
+    };
+    let author = CommitAuthor {
+        name: name.clone(),
+        email: email.clone(),
+        date: None,
+    };
+    builder.commiter(author.clone()).author(author)
+}
+
+/// 使用 personal token 创建 github 客户端，urls 为 github.com 或 Enterprise Server 的地址
+pub fn github_client(urls: &GithubUrls, token: &str) -> anyhow::Result<Octocrab> {
+    Ok(Octocrab::builder()
+        .base_uri(urls.api.as_str())?
+        .personal_token(token.to_string())
+        .build()?)
+}
+
+impl PushImage {
+    pub fn new(urls: &GithubUrls,token:&str,owner:&str,repo:&str) -> anyhow::Result<Self> {
+        let octocrab = github_client(urls, token)?;
+        Ok(Self::from_client(octocrab, owner, repo).with_urls(urls))
+    }
+
+    /// 以 github app 的身份推送，未配置提交者时由 github 使用 app 的 bot 账户提交
+    pub fn from_app(urls: &GithubUrls, app: &GithubApp, owner: &str, repo: &str) -> anyhow::Result<Self> {
+        let octocrab = app.client(urls)?;
+        let mut push_image = Self::from_client(octocrab, owner, repo).with_urls(urls);
+        push_image.app_auth = true;
+        Ok(push_image)
+    }
+
+    /// 使用已经创建好的 github 客户端
+    pub fn from_client(octocrab: Octocrab, owner: &str, repo: &str) -> Self {
+        let config = PusherConfig::default();
+        let workflow = None;
+        let workflow_input = None;
+        let urls = GithubUrls::from_host(remote::DEFAULT_GITHUB_HOST);
+        let app_auth = false;
+        let provenance = ProvenanceMode::Off;
+        let records = Vec::new();
+        let repo = repo.to_string();
+        let owner = owner.to_string();
+
+        Self { octocrab, repo, owner, config, workflow, workflow_input, urls, app_auth, provenance, records }
+    }
+
+    /// 设置 github 的主机名和网页地址，用于生成 noreply 邮箱和 clone 地址
+    pub fn with_urls(mut self, urls: &GithubUrls) -> Self {
+        self.urls = urls.clone();
+        self
+    }
+
+    /// 更新 images.txt 时记录同步历史，records 中的 requester 为空时使用提交者
+    pub fn with_provenance(mut self, mode: ProvenanceMode, records: Vec<MirrorRecord>) -> Self {
+        self.provenance = mode;
+        self.records = records;
+        self
+    }
+
+    /// 设置 workflow_dispatch 使用的 workflow 文件名和 input 名称，空字符串表示使用默认值
+    pub fn with_workflow(mut self, workflow: &str, input: &str) -> Self {
+        self.workflow = Some(workflow.to_string()).filter(|v| !v.is_empty());
+        self.workflow_input = Some(input.to_string()).filter(|v| !v.is_empty());
+        self
+    }
+
+    /// 通过 workflow_dispatch 触发 workflow，镜像列表作为 input 传入，不产生提交
+    pub async fn dispatch_workflow(&self, images: &[String]) -> anyhow::Result<Trigger> {
+        let branch = self.config.branch();
+        let workflow = self.workflow.clone().map_or(DEFAULT_WORKFLOW.into(), |v| v);
+        let input = self.workflow_input.clone().map_or(DEFAULT_WORKFLOW_INPUT.into(), |v| v);
+        let mut inputs = Map::new();
+        inputs.insert(input, Value::String(images.join("\n")));
+
+        // 记录调用时分支的提交和调用的账户，用于找到这次触发的 run，github app 的 bot 账户无法通过接口获取
+        let head_sha = self
+            .octocrab
+            .commits(self.owner.as_str(), self.repo.as_str())
+            .get(branch.as_str())
+            .await?
+            .sha;
+        let actor = if self.app_auth {
+            None
+        } else {
+            Some(self.octocrab.current().user().await?.login)
+        };
+
+        let since = chrono::Utc::now();
+        self.octocrab
+            .actions()
+            .create_workflow_dispatch(self.owner.as_str(), self.repo.as_str(), workflow.as_str(), branch.as_str())
+            .inputs(Value::Object(inputs))
+            .send()
+            .await?;
+        Ok(Trigger::Dispatch { workflow, branch, head_sha, actor, since })
+    }
+
+    /// 读取 images.txt 的内容和 blob sha
+    async fn read_image_file(&self, path: &str, branch: &str) -> anyhow::Result<(String, String)> {
+        let mut c = self.octocrab
+            .repos(self.owner.as_str(), self.repo.as_str())
+            .get_content()
+            .path(path)
+            .r#ref(branch)
+            .send()
+            .await?;
+        let current = c.items.remove(0);
+        Ok((current.decoded_content().unwrap_or_default(), current.sha))
+    }
+
+    /// 按读取时的 blob sha 更新 images.txt，sha 过期时返回 `Err(错误信息)`
+    async fn write_image_file(
+        &self,
+        path: &str,
+        branch: &str,
+        message: &str,
+        identity: &Option<(String, String)>,
+        content: String,
+        sha: String,
+    ) -> anyhow::Result<Result<String, String>> {
+        let repos = self.octocrab.repos(self.owner.as_str(), self.repo.as_str());
+        let update = repos.update_file(path, message, content, &sha).branch(branch);
+        match with_identity(update, identity).send().await {
+            Ok(update) => Ok(Ok(update.commit.sha.ok_or_else(|| anyhow::anyhow!("github did not return the commit sha"))?)),
+            Err(octocrab::Error::GitHub { source, .. }) if is_sha_conflict(source.status_code, &source.message) => {
+                Ok(Err(source.message))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// 更新 images.txt，返回产生的 commit sha
+    pub async fn update_image_file(&self, images: &[String],git_user_name:Option<String>,git_user_email:Option<String>) -> anyhow::Result<String> {
+        let path = self.config.path();
+        let branch = self.config.branch();
+        let identity = self.commit_identity(git_user_name, git_user_email).await?;
+        let records = self.provenance_records(&identity);
+
+        let mut message = format!("sync {}", images.join(", "));
+        if self.provenance == ProvenanceMode::Trailer && !records.is_empty() {
+            message = format!("{message}\n\n{}", provenance::trailers(&records)?);
+        }
+        let sha = update_image_lines(
+            &path,
+            images,
+            || self.read_image_file(&path, &branch),
+            |content, sha| self.write_image_file(&path, &branch, &message, &identity, content, sha),
+        )
+        .await?;
+        if self.provenance == ProvenanceMode::Log && !records.is_empty() {
+            self.append_mirror_log(&records, &identity, &branch).await?;
+        }
+        Ok(sha)
+    }
+
+    /// 本次同步的记录，requester 为空时使用提交者，app 没有提交者时使用 github-app
+    fn provenance_records(&self, identity: &Option<(String, String)>) -> Vec<MirrorRecord> {
+        let requester = identity.as_ref().map_or("github-app", |(name, _)| name.as_str());
+        self.records
+            .iter()
+            .cloned()
+            .map(|mut record| {
+                if record.requester.is_empty() {
+                    record.requester = requester.to_string();
+                }
+                record
+            })
+            .collect()
+    }
+
+    /// 把记录追加到 mirror-log.jsonl，提交信息带有 `[skip ci]`，不会再次触发 workflow
+    async fn append_mirror_log(
+        &self,
+        records: &[MirrorRecord],
+        identity: &Option<(String, String)>,
+        branch: &str,
+    ) -> anyhow::Result<()> {
+        let repos = self.octocrab.repos(self.owner.as_str(), self.repo.as_str());
+        let sources = records.iter().map(|r| r.source.as_str()).collect::<Vec<_>>();
+        let message = format!("record mirror of {} [skip ci]", sources.join(", "));
+
+        for attempt in 1..=MAX_UPDATE_ATTEMPTS {
+            let current = match repos.get_content().path(provenance::LOG_FILE).r#ref(branch).send().await {
+                Ok(mut c) => Some(c.items.remove(0)),
+                Err(octocrab::Error::GitHub { source, .. }) if source.status_code == StatusCode::NOT_FOUND => None,
+                Err(e) => return Err(e.into()),
+            };
+            let existing = current.as_ref().and_then(|c| c.decoded_content()).unwrap_or_default();
+            let content = provenance::append_log(&existing, records)?;
+
+            let update = match &current {
+                Some(current) => repos.update_file(provenance::LOG_FILE, &message, content, &current.sha),
+                None => repos.create_file(provenance::LOG_FILE, &message, content),
+            };
+            match with_identity(update.branch(branch), identity).send().await {
+                Ok(_) => return Ok(()),
+                Err(octocrab::Error::GitHub { source, .. }) if is_sha_conflict(source.status_code, &source.message) => {
+                    tracing::debug!("{} changed while updating (attempt {attempt}): {}", provenance::LOG_FILE, source.message);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        anyhow::bail!(
+            "{} kept changing while updating, gave up after {MAX_UPDATE_ATTEMPTS} attempts",
+            provenance::LOG_FILE
+        )
+    }
+
+    /// 读取 mirror-log.jsonl 和 images.txt 提交信息中的同步记录，按时间倒序排列
+    pub async fn history(&self, filter: &HistoryFilter) -> anyhow::Result<Vec<MirrorRecord>> {
+        let path = self.config.path();
+        let branch = self.config.branch();
+        let repos = self.octocrab.repos(self.owner.as_str(), self.repo.as_str());
+
+        let mut records = match repos.get_content().path(provenance::LOG_FILE).r#ref(&branch).send().await {
+            Ok(mut c) => provenance::parse_log(&c.items.remove(0).decoded_content().unwrap_or_default()),
+            Err(octocrab::Error::GitHub { source, .. }) if source.status_code == StatusCode::NOT_FOUND => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut commits = repos.list_commits().branch(&branch).path(&path).per_page(100u8);
+        if let Some(since) = filter.since {
+            commits = commits.since(since);
+        }
+        // 接口按 since 过滤提交，逐页读取到最后一页
+        let commits = self.octocrab.all_pages(commits.send().await?).await?;
+        records.extend(commits.iter().flat_map(|c| provenance::parse_trailers(&c.commit.message)));
+
+        records.retain(|r| filter.matches(r));
+        records.sort_by_key(|r| std::cmp::Reverse(r.timestamp));
+        Ok(records)
+    }
+}
+
+#[async_trait]
+impl PusherRepo for PushImage {
+    fn config(&self) -> &PusherConfig {
+        &self.config
+    }
+
+    fn config_mut(&mut self) -> &mut PusherConfig {
+        &mut self.config
+    }
+
+    fn clone_url(&self) -> String {
+        format!("{}/{}/{}.git", self.urls.web, self.owner, self.repo)
+    }
+
+    /// token 对应的 github 用户及其 noreply 邮箱。
+    /// github app 没有对应的用户，返回 None 由 github 使用 app 的 bot 账户提交
+    async fn account(&self) -> anyhow::Result<Option<Account>> {
+        if self.app_auth {
+            return Ok(None);
+        }
+        let user = self.octocrab.current().user().await?;
+        Ok(Some(Account {
+            email: Some(format!("{}+{}@users.noreply.{}", user.id, user.login, self.urls.host)),
+            name: user.login,
+        }))
+    }
+}
+
+#[async_trait]
+impl Pusher for PushImage {
+    async fn push(&self, mode: PushMode, images: &[String]) -> anyhow::Result<Trigger> {
+        match mode {
+            PushMode::Commit => {
+                let sha = self.update_image_file(images, None, None).await?;
+                Ok(Trigger::Commit { sha })
+            }
+            PushMode::Dispatch => self.dispatch_workflow(images).await,
+            PushMode::Git => {
+                let sha = self.push_git(images).await?;
+                Ok(Trigger::Commit { sha })
+            }
+        }
+    }
+
+    async fn wait_for_run(
+        &self,
+        trigger: &Trigger,
+        timeout: Duration,
+    ) -> anyhow::Result<Result<(), RunFailure>> {
+        let run = workflow::wait_for_run(&self.octocrab, &self.owner, &self.repo, trigger, timeout).await?;
+        Ok(run.map(|_| ()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        extract::{Query, State},
+        http::{header, HeaderMap, StatusCode},
+        routing::{get, post},
+        Json, Router,
+    };
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde_json::json;
+
+    use super::*;
+
+    /// 模拟 github contents 接口，sha 过期时返回 409
+    struct Contents {
+        sha: String,
+        content: String,
+    }
+
+    fn content_json(c: &Contents) -> Value {
+        json!({
+            "name": "images.txt",
+            "path": "images.txt",
+            "sha": c.sha,
+            "size": c.content.len(),
+            "url": "http://localhost/images.txt",
+            "type": "file",
+            "encoding": "base64",
+            "content": STANDARD.encode(&c.content),
+            "_links": { "self": "http://localhost/images.txt" }
+        })
+    }
+
+    async fn mock_github(state: Arc<Mutex<Contents>>) -> String {
+        let app = Router::new()
+            .route(
+                "/repos/owner/repo/contents/images.txt",
+                get(|State(state): State<Arc<Mutex<Contents>>>| async move {
+                    Json(content_json(&state.lock().unwrap()))
+                })
+                .put(
+                    |State(state): State<Arc<Mutex<Contents>>>, Json(body): Json<Value>| async move {
+                        let mut c = state.lock().unwrap();
+                        if body["sha"] != c.sha {
+                            let message = format!("images.txt does not match {}", c.sha);
+                            return (StatusCode::CONFLICT, Json(json!({ "message": message })));
+                        }
+                        let content = STANDARD.decode(body["content"].as_str().unwrap()).unwrap();
+                        c.content = String::from_utf8(content).unwrap();
+                        c.sha = "c".into();
+                        (
+                            StatusCode::OK,
+                            Json(json!({ "content": content_json(&c), "commit": { "sha": "commit1" } })),
+                        )
+                    },
+                ),
+            )
+            .route(
+                "/user",
+                get(|| async {
+                    let url = "http://localhost/users/alice";
+                    let mut user = json!({ "login": "alice", "id": 42, "node_id": "U_1", "gravatar_id": "", "type": "User", "site_admin": false });
+                    for key in [
+                        "avatar_url", "url", "html_url", "followers_url", "following_url", "gists_url", "starred_url",
+                        "subscriptions_url", "organizations_url", "repos_url", "events_url", "received_events_url",
+                    ] {
+                        user[key] = json!(url);
+                    }
+                    Json(user)
+                }),
+            )
+            .with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    fn client(base: String) -> PushImage {
+        let octocrab = Octocrab::builder().base_uri(base).unwrap().build().unwrap();
+        PushImage::from_client(octocrab, "owner", "repo")
+    }
+
+    #[tokio::test]
+    async fn test_write_image_file_conflict() {
+        let state = Arc::new(Mutex::new(Contents { sha: "b".into(), content: "redis:7".into() }));
+        let push_image = client(mock_github(state.clone()).await);
+
+        let current = push_image.read_image_file("images.txt", "main").await.unwrap();
+        assert_eq!(current, ("redis:7".to_string(), "b".to_string()));
+
+        let identity = Some(("bot".to_string(), "bot@example.com".to_string()));
+        let write = |sha: &str| {
+            push_image.write_image_file("images.txt", "main", "sync nginx:1.27", &identity, "nginx:1.27".into(), sha.into())
+        };
+        let conflict = write("a").await.unwrap();
+        assert!(conflict.is_err_and(|message| message.contains("does not match")));
+        assert_eq!(write("b").await.unwrap(), Ok("commit1".to_string()));
+        assert_eq!(state.lock().unwrap().content, "nginx:1.27");
+    }
+
+    #[tokio::test]
+    async fn test_account() {
+        let state = Arc::new(Mutex::new(Contents { sha: "a".into(), content: String::new() }));
+        let push_image = client(mock_github(state).await);
+
+        let account = push_image.account().await.unwrap();
+        let email = "42+alice@users.noreply.github.com".to_string();
+        assert_eq!(account, Some(Account { name: "alice".into(), email: Some(email) }));
+
+        // github app 没有对应的用户，由 github 使用 bot 账户
+        let mut app = push_image;
+        app.app_auth = true;
+        assert_eq!(app.account().await.unwrap(), None);
+    }
+
+    /// 一个 images.txt 的提交，提交信息中带有同步记录
+    fn commit_json(base: &str, sha: &str, source: &str) -> Value {
+        let url = format!("{base}/repos/owner/repo/commits/{sha}");
+        let mut record = MirrorRecord::new(source, None, &format!("swr.cn-south-1.myhuaweicloud.com/ns/{source}"));
+        record.timestamp = provenance::parse_since(&format!("2024-12-0{sha}")).unwrap();
+        let message = format!("sync {source}\n\n{}", provenance::trailers(&[record]).unwrap());
+        json!({
+            "url": url, "sha": sha, "node_id": sha, "html_url": url, "comments_url": url,
+            "commit": {
+                "url": url, "author": null, "committer": null, "message": message,
+                "comment_count": 0, "tree": { "sha": sha, "url": url },
+            },
+            "author": null, "committer": null, "parents": [],
+        })
+    }
+
+    #[tokio::test]
+    async fn test_history_reads_all_pages() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new()
+            .route(
+                "/repos/owner/repo/contents/mirror-log.jsonl",
+                get(|| async { (StatusCode::NOT_FOUND, Json(json!({ "message": "Not Found", "documentation_url": "" }))) }),
+            )
+            .route(
+                "/repos/owner/repo/commits",
+                get(|State(base): State<String>, Query(query): Query<HashMap<String, String>>| async move {
+                    if query.get("page").map(|p| p.as_str()) == Some("2") {
+                        return (HeaderMap::new(), Json(json!([commit_json(&base, "1", "redis:7")])));
+                    }
+                    let mut headers = HeaderMap::new();
+                    let next = format!("<{base}/repos/owner/repo/commits?page=2>; rel=\"next\"");
+                    headers.insert(header::LINK, next.parse().unwrap());
+                    (headers, Json(json!([commit_json(&base, "2", "nginx:1.27")])))
+                }),
+            )
+            .with_state(base.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let octocrab = Octocrab::builder().base_uri(base).unwrap().build().unwrap();
+        let push_image = PushImage::from_client(octocrab, "owner", "repo");
+        let records = push_image.history(&HistoryFilter::default()).await.unwrap();
+        let sources = records.iter().map(|r| r.source.as_str()).collect::<Vec<_>>();
+        assert_eq!(sources, ["nginx:1.27", "redis:7"]);
+    }
+
+    #[tokio::test]
+    async fn test_app_client_uses_installation_token() {
+        let seen = Arc::new(Mutex::new(Vec::<String>::new()));
+        let app = Router::new()
+            .route(
+                "/app/installations/42/access_tokens",
+                post(|headers: HeaderMap| async move {
+                    // 申请 installation token 时使用 app 的 JWT
+                    let auth = headers[header::AUTHORIZATION].to_str().unwrap().to_string();
+                    assert!(auth.starts_with("Bearer ey"), "{auth}");
+                    let expires_at = (chrono::Utc::now() + chrono::Duration::hours(1)).to_rfc3339();
+                    (StatusCode::CREATED, Json(json!({ "token": "ghs_installation", "expires_at": expires_at, "permissions": {} })))
+                }),
+            )
+            .route(
+                "/repos/owner/repo",
+                get(|State(seen): State<Arc<Mutex<Vec<String>>>>, headers: HeaderMap| async move {
+                    let auth = headers[header::AUTHORIZATION].to_str().unwrap().to_string();
+                    seen.lock().unwrap().push(auth);
+                    Json(json!({}))
+                }),
+            )
+            .with_state(seen.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let app = GithubApp {
+            app_id: 1,
+            private_key_path: PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src/pusher/testdata/github_app.pem")),
+            installation_id: 42,
+        };
+        let octocrab = app.client_at(format!("http://{addr}")).unwrap();
+        let _: Value = octocrab.get("/repos/owner/repo", None::<&()>).await.unwrap();
+
+        assert_eq!(seen.lock().unwrap().as_slice(), ["Bearer ghs_installation"]);
+    }
+}
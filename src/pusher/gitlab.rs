@@ -0,0 +1,490 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use reqwest::{header, Method, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    image::PushMode,
+    workflow::{self, RunFailure, Trigger},
+};
+
+use super::{check, error_message, update_image_lines, Account, Pusher, PusherConfig, PusherRepo};
+
+/// 未设置时传递镜像列表的 CI/CD 变量名
+const DEFAULT_VARIABLE: &str = "IMAGES";
+/// pipeline 结束时的状态
+const FINISHED_STATUSES: [&str; 4] = ["success", "failed", "canceled", "skipped"];
+
+/// 通过 gitlab 的 api 提交 images.txt 或者触发 pipeline
+pub struct GitlabPusher {
+    http_client: reqwest::Client,
+    base_url: String,
+    token: String,
+    trigger_token: Option<String>,
+    /// 完整的项目路径，可以包含子群组，如 `group/sub/docker_image_pusher`
+    project: String,
+    config: PusherConfig,
+    variable: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FileResponse {
+    #[serde(default)]
+    content: String,
+    last_commit_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Commit {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    username: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    commit_email: Option<String>,
+    #[serde(default)]
+    email: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Pipeline {
+    id: u64,
+    status: String,
+    #[serde(default)]
+    web_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Job {
+    id: u64,
+    name: String,
+    stage: String,
+}
+
+impl GitlabPusher {
+    /// base_url 为实例的网页地址，如 `https://gitlab.com`，project 为包含群组的完整路径
+    pub fn new(base_url: &str, token: &str, project: &str) -> Self {
+        Self {
+            http_client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            trigger_token: None,
+            project: project.trim_matches('/').to_string(),
+            config: PusherConfig::default(),
+            variable: None,
+        }
+    }
+
+    /// 设置 pipeline trigger token，空字符串表示使用 access token 创建 pipeline
+    pub fn with_trigger_token(mut self, token: &str) -> Self {
+        self.trigger_token = Some(token.to_string()).filter(|v| !v.is_empty());
+        self
+    }
+
+    /// 设置触发 pipeline 时传递镜像列表的变量名，空字符串表示使用默认值 IMAGES
+    pub fn with_variable(mut self, variable: &str) -> Self {
+        self.variable = Some(variable.to_string()).filter(|v| !v.is_empty());
+        self
+    }
+
+    /// 项目相关的接口，项目路径需要整体编码
+    fn project_route(&self, route: &str) -> String {
+        format!("/projects/{}{route}", urlencoding::encode(&self.project))
+    }
+
+    fn request(&self, method: Method, route: &str) -> RequestBuilder {
+        self.http_client
+            .request(method, format!("{}/api/v4{route}", self.base_url))
+            .header("PRIVATE-TOKEN", &self.token)
+            .header(header::ACCEPT, "application/json")
+    }
+
+    /// 读取 images.txt 的内容和最后修改它的提交
+    async fn read_image_file(&self, path: &str, branch: &str) -> anyhow::Result<(String, String)> {
+        let route = self.project_route(&format!("/repository/files/{}", urlencoding::encode(path)));
+        let response = self.request(Method::GET, &route).query(&[("ref", branch)]).send().await?;
+        let current: FileResponse = check(response).await?.json().await?;
+        Ok((String::from_utf8(STANDARD.decode(current.content.trim())?)?, current.last_commit_id))
+    }
+
+    /// 通过 Commits 接口更新 images.txt，last_commit_id 过期时返回 `Err(错误信息)`
+    async fn write_image_file(
+        &self,
+        path: &str,
+        body: &Value,
+        content: String,
+        last_commit_id: String,
+    ) -> anyhow::Result<Result<String, String>> {
+        // last_commit_id 和分支上最后修改文件的提交不一致时 gitlab 拒绝更新
+        let mut body = body.clone();
+        body["actions"] = json!([{
+            "action": "update",
+            "file_path": path,
+            "content": content,
+            "last_commit_id": last_commit_id,
+        }]);
+        let response = self
+            .request(Method::POST, &self.project_route("/repository/commits"))
+            .json(&body)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if status.is_success() {
+            let commit: Commit = response.json().await?;
+            return Ok(Ok(commit.id));
+        }
+        let message = error_message(response).await;
+        if !is_file_conflict(status, &message) {
+            anyhow::bail!("update {path} failed ({status}): {message}");
+        }
+        Ok(Err(message))
+    }
+
+    /// 通过 Commits 接口更新 images.txt，返回产生的 commit sha
+    pub async fn update_image_file(
+        &self,
+        images: &[String],
+        git_user_name: Option<String>,
+        git_user_email: Option<String>,
+    ) -> anyhow::Result<String> {
+        let path = self.config.path();
+        let branch = self.config.branch();
+        let identity = self.commit_identity(git_user_name, git_user_email).await?;
+
+        let mut body = json!({
+            "branch": branch,
+            "commit_message": format!("sync {}", images.join(", ")),
+        });
+        if let Some((name, email)) = &identity {
+            body["author_name"] = json!(name);
+            body["author_email"] = json!(email);
+        }
+        update_image_lines(
+            &path,
+            images,
+            || self.read_image_file(&path, &branch),
+            |content, last_commit_id| self.write_image_file(&path, &body, content, last_commit_id),
+        )
+        .await
+    }
+
+    /// 触发 pipeline，镜像列表作为变量传入，不产生提交。
+    /// 有 trigger token 时使用 pipeline trigger 接口，否则使用 access token 创建 pipeline
+    pub async fn trigger_pipeline(&self, images: &[String]) -> anyhow::Result<Trigger> {
+        let branch = self.config.branch();
+        let variable = self.variable.clone().map_or(DEFAULT_VARIABLE.into(), |v| v);
+        let value = images.join("\n");
+
+        let request = match &self.trigger_token {
+            Some(token) => self
+                .request(Method::POST, &self.project_route("/trigger/pipeline"))
+                .form(&[
+                    ("token", token.as_str()),
+                    ("ref", branch.as_str()),
+                    (&format!("variables[{variable}]"), value.as_str()),
+                ]),
+            None => self
+                .request(Method::POST, &self.project_route("/pipeline"))
+                .json(&json!({
+                    "ref": branch,
+                    "variables": [{ "key": variable, "value": value }],
+                })),
+        };
+        let pipeline: Pipeline = check(request.send().await?).await?.json().await?;
+        Ok(Trigger::Pipeline { id: pipeline.id })
+    }
+
+    /// 查找触发对应的 pipeline，还没创建时返回 None
+    async fn find_pipeline(&self, trigger: &Trigger) -> anyhow::Result<Option<Pipeline>> {
+        match trigger {
+            Trigger::Commit { sha } => {
+                let response = self
+                    .request(Method::GET, &self.project_route("/pipelines"))
+                    .query(&[("sha", sha.as_str())])
+                    .send()
+                    .await?;
+                let pipelines: Vec<Pipeline> = check(response).await?.json().await?;
+                // 接口返回的 pipeline 按 id 倒序排列，取最早创建的那个
+                Ok(pipelines.into_iter().min_by_key(|p| p.id))
+            }
+            Trigger::Pipeline { id } => Ok(Some(self.get_pipeline(*id).await?)),
+            Trigger::Dispatch { workflow, .. } => {
+                anyhow::bail!("workflow {workflow} is not a gitlab pipeline")
+            }
+        }
+    }
+
+    async fn get_pipeline(&self, id: u64) -> anyhow::Result<Pipeline> {
+        let response = self
+            .request(Method::GET, &self.project_route(&format!("/pipelines/{id}")))
+            .send()
+            .await?;
+        Ok(check(response).await?.json().await?)
+    }
+
+    async fn collect_failure(&self, pipeline: Pipeline) -> anyhow::Result<RunFailure> {
+        let response = self
+            .request(Method::GET, &self.project_route(&format!("/pipelines/{}/jobs", pipeline.id)))
+            .query(&[("scope[]", "failed")])
+            .send()
+            .await?;
+        let jobs: Vec<Job> = check(response).await?.json().await?;
+
+        let Some(job) = jobs.into_iter().next() else {
+            return Ok(RunFailure {
+                url: pipeline.web_url,
+                job: None,
+                step: None,
+                log_tail: Vec::new(),
+            });
+        };
+
+        let response = self
+            .request(Method::GET, &self.project_route(&format!("/jobs/{}/trace", job.id)))
+            .send()
+            .await?;
+        let log = check(response).await?.text().await?;
+
+        Ok(RunFailure {
+            url: pipeline.web_url,
+            job: Some(job.name),
+            step: Some(job.stage),
+            log_tail: workflow::log_tail(&log, workflow::LOG_TAIL_LINES),
+        })
+    }
+}
+
+#[async_trait]
+impl PusherRepo for GitlabPusher {
+    fn config(&self) -> &PusherConfig {
+        &self.config
+    }
+
+    fn config_mut(&mut self) -> &mut PusherConfig {
+        &mut self.config
+    }
+
+    fn clone_url(&self) -> String {
+        format!("{}/{}.git", self.base_url, self.project)
+    }
+
+    /// token 对应的用户，优先使用显示名和 commit email
+    async fn account(&self) -> anyhow::Result<Option<Account>> {
+        let user: User = check(self.request(Method::GET, "/user").send().await?).await?.json().await?;
+        Ok(Some(Account {
+            name: if user.name.is_empty() { user.username } else { user.name },
+            email: user
+                .commit_email
+                .filter(|v| !v.is_empty())
+                .or(user.email.filter(|v| !v.is_empty())),
+        }))
+    }
+}
+
+#[async_trait]
+impl Pusher for GitlabPusher {
+    async fn push(&self, mode: PushMode, images: &[String]) -> anyhow::Result<Trigger> {
+        match mode {
+            PushMode::Commit => {
+                let sha = self.update_image_file(images, None, None).await?;
+                Ok(Trigger::Commit { sha })
+            }
+            PushMode::Dispatch => self.trigger_pipeline(images).await,
+            PushMode::Git => {
+                let sha = self.push_git(images).await?;
+                Ok(Trigger::Commit { sha })
+            }
+        }
+    }
+
+    /// 轮询 pipeline 状态直到结束，和 github 的 workflow run 一样输出失败的 job 和日志尾部
+    async fn wait_for_run(
+        &self,
+        trigger: &Trigger,
+        timeout: Duration,
+    ) -> anyhow::Result<Result<(), RunFailure>> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let spinner = cliclack::spinner();
+        spinner.start("waiting for pipeline to start...");
+
+        let mut pipeline_id = None;
+        loop {
+            if tokio::time::Instant::now() >= deadline {
+                spinner.error("timed out waiting for pipeline");
+                anyhow::bail!("timed out after {}s waiting for pipeline", timeout.as_secs());
+            }
+
+            let pipeline = match pipeline_id {
+                Some(id) => Some(self.get_pipeline(id).await?),
+                None => self.find_pipeline(trigger).await?,
+            };
+
+            if let Some(pipeline) = pipeline {
+                pipeline_id = Some(pipeline.id);
+                if FINISHED_STATUSES.contains(&pipeline.status.as_str()) {
+                    if pipeline.status == "success" {
+                        spinner.stop(format!("pipeline succeeded: {}", pipeline.web_url));
+                        return Ok(Ok(()));
+                    }
+                    spinner.error(format!("pipeline {}: {}", pipeline.status, pipeline.web_url));
+                    return Ok(Err(self.collect_failure(pipeline).await?));
+                }
+                spinner.set_message(format!("pipeline #{} is {}...", pipeline.id, pipeline.status));
+            }
+
+            tokio::time::sleep(workflow::POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// last_commit_id 过期时 gitlab 返回 400 并提示文件已经被修改
+fn is_file_conflict(status: StatusCode, message: &str) -> bool {
+    status == StatusCode::CONFLICT || (status == StatusCode::BAD_REQUEST && message.contains("changed"))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        extract::{Form, State},
+        http::StatusCode,
+        routing::{get, post},
+        Json, Router,
+    };
+    use super::*;
+
+    /// 模拟子群组中项目的 files、commits、pipeline 和 job 接口，last_commit_id 过期时返回 400
+    #[derive(Default)]
+    struct Project {
+        last_commit_id: String,
+        content: String,
+        trigger: Option<HashMap<String, String>>,
+    }
+
+    type Shared = Arc<Mutex<Project>>;
+
+    const PROJECT: &str = "/api/v4/projects/group%2Fsub%2Fpusher";
+
+    async fn mock_gitlab(state: Shared) -> String {
+        let app = Router::new()
+            .route(
+                &format!("{PROJECT}/repository/files/images.txt"),
+                get(|State(state): State<Shared>| async move {
+                    let c = state.lock().unwrap();
+                    Json(json!({ "content": STANDARD.encode(&c.content), "last_commit_id": c.last_commit_id }))
+                }),
+            )
+            .route(
+                &format!("{PROJECT}/repository/commits"),
+                post(|State(state): State<Shared>, Json(body): Json<Value>| async move {
+                    let mut c = state.lock().unwrap();
+                    let action = &body["actions"][0];
+                    assert_eq!((&action["action"], &action["file_path"]), (&json!("update"), &json!("images.txt")));
+                    if action["last_commit_id"] != c.last_commit_id {
+                        let message = "You are attempting to update a file that has changed since you started editing it.";
+                        return (StatusCode::BAD_REQUEST, Json(json!({ "message": message })));
+                    }
+                    c.content = action["content"].as_str().unwrap().to_string();
+                    c.last_commit_id = "commit1".into();
+                    (StatusCode::CREATED, Json(json!({ "id": c.last_commit_id, "short_id": "commit" })))
+                }),
+            )
+            .route(
+                "/api/v4/user",
+                get(|| async {
+                    Json(json!({ "username": "alice", "name": "Alice", "commit_email": "alice@example.com", "email": "" }))
+                }),
+            )
+            .route(
+                &format!("{PROJECT}/trigger/pipeline"),
+                post(|State(state): State<Shared>, Form(form): Form<HashMap<String, String>>| async move {
+                    state.lock().unwrap().trigger = Some(form);
+                    (StatusCode::CREATED, Json(json!({ "id": 7, "status": "created", "web_url": "http://gitlab/p/7" })))
+                }),
+            )
+            .route(
+                &format!("{PROJECT}/pipelines/7"),
+                get(|| async { Json(json!({ "id": 7, "status": "failed", "web_url": "http://gitlab/p/7" })) }),
+            )
+            .route(
+                &format!("{PROJECT}/pipelines/7/jobs"),
+                get(|| async { Json(json!([{ "id": 11, "name": "sync", "stage": "deploy" }])) }),
+            )
+            .route(
+                &format!("{PROJECT}/jobs/11/trace"),
+                get(|| async { "pulling nginx\nerror: manifest unknown" }),
+            )
+            .with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn test_write_image_file_conflict() {
+        let state = Arc::new(Mutex::new(Project {
+            last_commit_id: "b".into(),
+            content: "redis:7".into(),
+            ..Default::default()
+        }));
+        let base = mock_gitlab(state.clone()).await;
+        let pusher = GitlabPusher::new(&base, "secret", "group/sub/pusher");
+
+        let current = pusher.read_image_file("images.txt", "main").await.unwrap();
+        assert_eq!(current, ("redis:7".to_string(), "b".to_string()));
+
+        // gitlab 在 last_commit_id 过期时返回 400
+        let body = json!({ "branch": "main", "commit_message": "sync nginx:1.27" });
+        let conflict = pusher.write_image_file("images.txt", &body, "nginx:1.27".into(), "a".into()).await.unwrap();
+        assert!(conflict.is_err_and(|message| message.contains("has changed")));
+        let sha = pusher.write_image_file("images.txt", &body, "nginx:1.27".into(), "b".into()).await.unwrap();
+        assert_eq!(sha, Ok("commit1".to_string()));
+        assert_eq!(state.lock().unwrap().content, "nginx:1.27");
+    }
+
+    #[tokio::test]
+    async fn test_account() {
+        let base = mock_gitlab(Arc::new(Mutex::new(Project::default()))).await;
+        let pusher = GitlabPusher::new(&base, "secret", "group/sub/pusher");
+
+        // 优先使用显示名和 commit email
+        let account = pusher.account().await.unwrap();
+        assert_eq!(account, Some(Account { name: "Alice".into(), email: Some("alice@example.com".into()) }));
+    }
+
+    #[tokio::test]
+    async fn test_trigger_and_wait_for_failed_pipeline() {
+        let state = Arc::new(Mutex::new(Project::default()));
+        let base = mock_gitlab(state.clone()).await;
+        let pusher = GitlabPusher::new(&base, "secret", "group/sub/pusher").with_trigger_token("trigger");
+
+        let trigger = pusher.push(PushMode::Dispatch, &["nginx:1.27".to_string()]).await.unwrap();
+        let form = state.lock().unwrap().trigger.clone().unwrap();
+        assert_eq!(form["token"], "trigger");
+        assert_eq!(form["ref"], "main");
+        assert_eq!(form["variables[IMAGES]"], "nginx:1.27");
+
+        let failure = pusher
+            .wait_for_run(&trigger, Duration::from_secs(10))
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(failure.url, "http://gitlab/p/7");
+        assert_eq!(failure.job.as_deref(), Some("sync"));
+        assert_eq!(failure.step.as_deref(), Some("deploy"));
+        assert_eq!(failure.log_tail.last().map(|s| s.as_str()), Some("error: manifest unknown"));
+    }
+}
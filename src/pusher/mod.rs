@@ -0,0 +1,351 @@
+use std::{future::Future, time::Duration};
+
+use async_trait::async_trait;
+use reqwest::{Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    git::{self, GitPusher},
+    image::{merge_image_lines, PushMode},
+    workflow::{RunFailure, Trigger},
+};
+
+pub mod gitea;
+pub mod github;
+pub mod gitlab;
+
+/// images.txt 的 sha 冲突时最多尝试的次数
+pub(crate) const MAX_UPDATE_ATTEMPTS: usize = 3;
+/// 未设置时 images.txt 所在的分支
+pub(crate) const DEFAULT_BRANCH: &str = "main";
+/// 未设置时镜像列表文件的路径
+pub(crate) const DEFAULT_IMAGE_FILE: &str = "images.txt";
+/// 未设置时 dispatch 模式触发的 workflow 文件名
+pub(crate) const DEFAULT_WORKFLOW: &str = "docker.yaml";
+/// 未设置时 dispatch 模式传递镜像列表的 input 名称
+pub(crate) const DEFAULT_WORKFLOW_INPUT: &str = "images";
+
+/// pusher 仓库所在的代码托管平台
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// github.com 或 GitHub Enterprise Server
+    #[default]
+    Github,
+    /// 开启了 Actions 的 Gitea 或 Forgejo
+    Gitea,
+    /// gitlab.com 或自建的 GitLab，通过 CI/CD pipeline 同步
+    Gitlab,
+}
+
+/// 把镜像列表交给 pusher 仓库，由仓库中的 workflow 完成同步
+#[async_trait]
+pub trait Pusher: Send + Sync {
+    /// 推送镜像并返回触发方式，用于后续等待运行结束，images 为 images.txt 中的行
+    async fn push(&self, mode: PushMode, images: &[String]) -> anyhow::Result<Trigger>;
+
+    /// 等待推送触发的运行结束，失败时返回失败的 job、step 和日志尾部
+    async fn wait_for_run(
+        &self,
+        trigger: &Trigger,
+        timeout: Duration,
+    ) -> anyhow::Result<Result<(), RunFailure>>;
+}
+
+/// 不访问平台 api 时的提交者，依次使用参数、`GIT_AUTHOR_NAME`/`GIT_AUTHOR_EMAIL` 环境变量、配置、本地 git 配置
+pub(crate) fn configured_identity(
+    name: Option<String>,
+    email: Option<String>,
+    author_name: Option<&str>,
+    author_email: Option<&str>,
+) -> (Option<String>, Option<String>) {
+    let git_config = git2::Config::open_default().ok();
+    let from_git = |key: &str| git_config.as_ref().and_then(|c| c.get_string(key).ok());
+
+    let name = name
+        .or_else(|| env_var("GIT_AUTHOR_NAME"))
+        .or_else(|| author_name.map(|v| v.to_string()))
+        .or_else(|| from_git("user.name"))
+        .filter(|v| !v.is_empty());
+    let email = email
+        .or_else(|| env_var("GIT_AUTHOR_EMAIL"))
+        .or_else(|| author_email.map(|v| v.to_string()))
+        .or_else(|| from_git("user.email"))
+        .filter(|v| !v.is_empty());
+    (name, email)
+}
+
+/// pusher 仓库中各平台共用的配置，None 表示使用默认值
+#[derive(Debug, Clone, Default)]
+pub struct PusherConfig {
+    pub(crate) branch: Option<String>,
+    pub(crate) path: Option<String>,
+    pub(crate) author_name: Option<String>,
+    pub(crate) author_email: Option<String>,
+    pub(crate) git_url: Option<String>,
+}
+
+impl PusherConfig {
+    /// images.txt 所在的分支
+    pub(crate) fn branch(&self) -> String {
+        self.branch.clone().unwrap_or(DEFAULT_BRANCH.into())
+    }
+
+    /// 镜像列表文件的路径
+    pub(crate) fn path(&self) -> String {
+        self.path.clone().unwrap_or(DEFAULT_IMAGE_FILE.into())
+    }
+}
+
+/// token 对应的账户，用于补全缺少的提交者
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub(crate) name: String,
+    pub(crate) email: Option<String>,
+}
+
+/// 各平台共用的提交者和 git 模式，平台只需提供配置、clone 地址和 token 对应的账户
+#[async_trait]
+pub trait PusherRepo: Send + Sync {
+    fn config(&self) -> &PusherConfig;
+
+    fn config_mut(&mut self) -> &mut PusherConfig;
+
+    /// 未设置 git_url 时 git 模式使用的 https 地址
+    fn clone_url(&self) -> String;
+
+    /// token 对应的账户，没有对应的账户时返回 None，由平台决定提交者
+    async fn account(&self) -> anyhow::Result<Option<Account>>;
+
+    /// 设置配置中的提交者，空字符串表示未配置
+    fn with_commit_author(mut self, name: &str, email: &str) -> Self
+    where
+        Self: Sized,
+    {
+        let config = self.config_mut();
+        config.author_name = Some(name.to_string()).filter(|v| !v.is_empty());
+        config.author_email = Some(email.to_string()).filter(|v| !v.is_empty());
+        self
+    }
+
+    /// 设置 git 模式使用的仓库地址，支持 ssh 和 https，空字符串表示使用平台上的 https 地址
+    fn with_git_url(mut self, url: &str) -> Self
+    where
+        Self: Sized,
+    {
+        self.config_mut().git_url = Some(url.to_string()).filter(|v| !v.is_empty());
+        self
+    }
+
+    /// 确定提交者，依次使用参数、`GIT_AUTHOR_NAME`/`GIT_AUTHOR_EMAIL` 环境变量、配置、本地 git 配置，
+    /// 都没有时使用 token 对应的账户
+    async fn commit_identity(
+        &self,
+        name: Option<String>,
+        email: Option<String>,
+    ) -> anyhow::Result<Option<(String, String)>> {
+        let config = self.config();
+        let (name, email) =
+            configured_identity(name, email, config.author_name.as_deref(), config.author_email.as_deref());
+        self.complete_identity(name, email).await
+    }
+
+    /// 用 token 对应的账户补全缺少的提交者
+    async fn complete_identity(
+        &self,
+        name: Option<String>,
+        email: Option<String>,
+    ) -> anyhow::Result<Option<(String, String)>> {
+        if let (Some(name), Some(email)) = (&name, &email) {
+            return Ok(Some((name.clone(), email.clone())));
+        }
+        let Some(account) = self.account().await? else {
+            return Ok(None);
+        };
+        let email = email.or(account.email).ok_or_else(|| {
+            anyhow::anyhow!("commit author email is not set, set commit_author_email or GIT_AUTHOR_EMAIL")
+        })?;
+        Ok(Some((name.unwrap_or(account.name), email)))
+    }
+
+    /// 在缓存的本地 clone 中更新 images.txt 并通过 git 协议推送，返回提交的 sha
+    async fn push_git(&self, images: &[String]) -> anyhow::Result<String> {
+        let config = self.config();
+        let url = config.git_url.clone().unwrap_or(self.clone_url());
+        let pusher = GitPusher::new(&url, &config.branch(), &config.path(), &git::cache_dir_for(&url)?);
+
+        // 和 commit 模式使用相同的提交者，没有对应的账户时使用 clone 中的 git 配置
+        let (name, email) = self.commit_identity(None, None).await?.unzip();
+        let images = images.to_vec();
+        tokio::task::spawn_blocking(move || pusher.push_images(&images, name, email)).await?
+    }
+}
+
+/// 更新镜像列表文件，返回产生的 commit sha。
+/// read 返回文件当前的内容和版本，write 按读取到的版本提交新内容，版本过期时返回 `Err(错误信息)`，
+/// 此时说明有人同时更新了文件，重新读取并把待同步的镜像合并到最新内容中
+pub(crate) async fn update_image_lines<R, RF, W, WF>(
+    path: &str,
+    images: &[String],
+    read: R,
+    write: W,
+) -> anyhow::Result<String>
+where
+    R: Fn() -> RF,
+    RF: Future<Output = anyhow::Result<(String, String)>>,
+    W: Fn(String, String) -> WF,
+    WF: Future<Output = anyhow::Result<Result<String, String>>>,
+{
+    let mut content = images.join("\n");
+    for attempt in 1..=MAX_UPDATE_ATTEMPTS {
+        let (current, version) = read().await?;
+        // 第一次直接覆盖
+        if attempt > 1 {
+            content = merge_image_lines(&current, images);
+        }
+        match write(content.clone(), version).await? {
+            Ok(sha) => return Ok(sha),
+            Err(message) => tracing::debug!("{path} changed while updating (attempt {attempt}): {message}"),
+        }
+    }
+
+    anyhow::bail!(
+        "{path} kept changing while updating, gave up after {MAX_UPDATE_ATTEMPTS} attempts; pending images: {}",
+        images.join(", ")
+    )
+}
+
+fn env_var(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|v| !v.is_empty())
+}
+
+/// 接口返回错误时带上状态码和错误信息
+pub(crate) async fn check(response: Response) -> anyhow::Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let url = response.url().clone();
+    anyhow::bail!("{url} failed ({status}): {}", error_message(response).await)
+}
+
+/// gitea 和 gitlab 的错误响应为 `{"message": ...}`，gitlab 的 message 可能是对象，不是 json 时返回原始内容
+pub(crate) async fn error_message(response: Response) -> String {
+    let text = response.text().await.unwrap_or_default();
+    match serde_json::from_str::<Value>(&text).ok().map(|v| v["message"].clone()) {
+        Some(Value::String(message)) => message,
+        Some(Value::Null) | None => text,
+        Some(message) => message.to_string(),
+    }
+}
+
+/// 文件的 blob sha 已经过期，接口返回 409，或者 422 并提示 sha 不匹配
+pub(crate) fn is_sha_conflict(status: StatusCode, message: &str) -> bool {
+    status == StatusCode::CONFLICT || (status == StatusCode::UNPROCESSABLE_ENTITY && message.contains("sha"))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// 账户固定的 pusher 仓库，记录查询账户的次数
+    #[derive(Default)]
+    struct Repo {
+        config: PusherConfig,
+        account: Option<Account>,
+        lookups: Mutex<usize>,
+    }
+
+    #[async_trait]
+    impl PusherRepo for Repo {
+        fn config(&self) -> &PusherConfig {
+            &self.config
+        }
+
+        fn config_mut(&mut self) -> &mut PusherConfig {
+            &mut self.config
+        }
+
+        fn clone_url(&self) -> String {
+            "https://git.example.com/owner/repo.git".into()
+        }
+
+        async fn account(&self) -> anyhow::Result<Option<Account>> {
+            *self.lookups.lock().unwrap() += 1;
+            Ok(self.account.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_complete_identity_from_account() {
+        let repo = Repo {
+            account: Some(Account { name: "alice".into(), email: Some("alice@example.com".into()) }),
+            ..Default::default()
+        };
+        // 已有完整的提交者时不查询账户
+        let identity = repo.complete_identity(Some("bot".into()), Some("bot@example.com".into())).await.unwrap();
+        assert_eq!(identity, Some(("bot".into(), "bot@example.com".into())));
+        assert_eq!(*repo.lookups.lock().unwrap(), 0);
+
+        let identity = repo.complete_identity(Some("bot".into()), None).await.unwrap();
+        assert_eq!(identity, Some(("bot".into(), "alice@example.com".into())));
+        let identity = repo.complete_identity(None, None).await.unwrap();
+        assert_eq!(identity, Some(("alice".into(), "alice@example.com".into())));
+
+        // 没有对应的账户时由平台决定提交者，账户没有邮箱时要求配置
+        let app = Repo::default();
+        assert_eq!(app.complete_identity(None, None).await.unwrap(), None);
+        let private = Repo { account: Some(Account { name: "alice".into(), email: None }), ..Default::default() };
+        assert!(private.complete_identity(None, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_update_image_lines_merges_on_conflict() {
+        let file = &Mutex::new(("alpine".to_string(), "a".to_string()));
+        let writes = &Mutex::new(0);
+        let read = || async move { Ok(file.lock().unwrap().clone()) };
+        let write = |content: String, version: String| async move {
+            let mut writes = writes.lock().unwrap();
+            let mut file = file.lock().unwrap();
+            *writes += 1;
+            // 第一次提交时模拟其他人先提交了 images.txt
+            if *writes == 1 {
+                *file = ("redis:7".into(), "b".into());
+            }
+            if version != file.1 {
+                return Ok(Err(format!("{version} is not {}", file.1)));
+            }
+            *file = (content, format!("commit{writes}"));
+            Ok(Ok(file.1.clone()))
+        };
+
+        let sha = update_image_lines("images.txt", &["nginx:1.27".to_string()], read, write).await.unwrap();
+        assert_eq!(sha, "commit2");
+        assert_eq!(*writes.lock().unwrap(), 2);
+        assert_eq!(file.lock().unwrap().0, "redis:7\nnginx:1.27");
+
+        // 一直冲突时放弃并列出未同步的镜像
+        let conflict = |_: String, _: String| async { Ok(Err("sha does not match".to_string())) };
+        let e = update_image_lines("images.txt", &["nginx:1.27".to_string()], read, conflict).await.unwrap_err();
+        assert!(e.to_string().contains("pending images: nginx:1.27"), "{e}");
+    }
+
+    #[test]
+    fn test_configured_identity_order() {
+        if env_var("GIT_AUTHOR_NAME").is_some() || env_var("GIT_AUTHOR_EMAIL").is_some() {
+            return;
+        }
+        // 参数优先于配置，参数中缺少的部分取配置
+        let (name, email) = configured_identity(Some("flag".into()), None, Some("conf"), Some("conf@example.com"));
+        assert_eq!(name.as_deref(), Some("flag"));
+        assert_eq!(email.as_deref(), Some("conf@example.com"));
+
+        let (name, email) =
+            configured_identity(None, Some("flag@example.com".into()), Some("conf"), Some("conf@example.com"));
+        assert_eq!(name.as_deref(), Some("conf"));
+        assert_eq!(email.as_deref(), Some("flag@example.com"));
+    }
+}
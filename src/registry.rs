@@ -0,0 +1,782 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::Context;
+use reqwest::{header, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::platform::Platform;
+
+/// docker v2 schema2 单平台 manifest
+pub const DOCKER_MANIFEST: &str = "application/vnd.docker.distribution.manifest.v2+json";
+/// docker 多平台 manifest list
+pub const DOCKER_MANIFEST_LIST: &str = "application/vnd.docker.distribution.manifest.list.v2+json";
+/// OCI 单平台 manifest
+pub const OCI_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+/// OCI 多平台 index
+pub const OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+
+/// 获取 manifest 时接受的类型，包括 docker v2 和 OCI 的单平台 manifest 及多平台 index
+pub const MANIFEST_ACCEPT: &str = "application/vnd.docker.distribution.manifest.v2+json, \
+application/vnd.docker.distribution.manifest.list.v2+json, \
+application/vnd.oci.image.manifest.v1+json, \
+application/vnd.oci.image.index.v1+json";
+
+const DOCKER_CONTENT_DIGEST: &str = "docker-content-digest";
+/// 列出标签时每页的数量
+const TAGS_PAGE_SIZE: usize = 100;
+
+/// 镜像仓库返回的 manifest
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub digest: String,
+    pub media_type: String,
+    pub body: Vec<u8>,
+}
+
+/// manifest 中引用的内容，HEAD 请求只能得到 digest、类型和大小
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    #[serde(default)]
+    pub media_type: String,
+    pub digest: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<IndexPlatform>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<HashMap<String, String>>,
+}
+
+/// 单平台镜像的 manifest，docker schema2 和 OCI 的结构相同
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageManifest {
+    #[serde(default)]
+    pub schema_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    pub config: Descriptor,
+    pub layers: Vec<Descriptor>,
+}
+
+/// 多平台的 manifest list 或 OCI index
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestIndex {
+    #[serde(default)]
+    pub schema_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    #[serde(default)]
+    pub manifests: Vec<Descriptor>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IndexPlatform {
+    pub architecture: String,
+    pub os: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+}
+
+impl std::fmt::Display for IndexPlatform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.variant {
+            Some(variant) => write!(f, "{}/{}/{variant}", self.os, self.architecture),
+            None => write!(f, "{}/{}", self.os, self.architecture),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TagList {
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+}
+
+impl Manifest {
+    pub fn is_index(&self) -> bool {
+        self.media_type.contains("manifest.list") || self.media_type.contains("image.index")
+    }
+
+    /// 解析为单平台 manifest
+    pub fn image_manifest(&self) -> anyhow::Result<ImageManifest> {
+        if self.is_index() {
+            anyhow::bail!(
+                "{} is a multi-platform index, not an image manifest",
+                self.digest
+            );
+        }
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
+    /// 解析为多平台 index
+    pub fn index(&self) -> anyhow::Result<ManifestIndex> {
+        if !self.is_index() {
+            anyhow::bail!(
+                "{} is an image manifest, not a multi-platform index",
+                self.digest
+            );
+        }
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
+    /// 多平台 index 中指定平台的 manifest digest
+    pub fn platform_digest(&self, platform: &Platform) -> Option<String> {
+        let index: ManifestIndex = serde_json::from_slice(&self.body).ok()?;
+        index
+            .manifests
+            .into_iter()
+            .find(|m| {
+                m.platform
+                    .as_ref()
+                    .is_some_and(|p| platform.matches(&p.os, &p.architecture, p.variant.as_deref()))
+            })
+            .map(|m| m.digest)
+    }
+}
+
+/// 开始上传 blob 的结果
+#[derive(Debug)]
+pub enum Upload {
+    /// 已从其他仓库挂载，不需要上传
+    Mounted,
+    Session(BlobUpload),
+}
+
+/// 进行中的分段上传，location 为下一段的上传地址，offset 为已上传的字节数
+#[derive(Debug)]
+pub struct BlobUpload {
+    location: String,
+    offset: u64,
+}
+
+impl BlobUpload {
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// 上传地址中的 scheme 和主机，用于补全相对地址
+    fn base(&self) -> String {
+        reqwest::Url::parse(&self.location)
+            .map(|url| url.origin().ascii_serialization())
+            .unwrap_or_default()
+    }
+}
+
+/// 镜像仓库 v2 接口的客户端，支持匿名、basic 和 bearer token 认证
+pub struct RegistryClient {
+    http_client: reqwest::Client,
+    credentials: Option<(String, String)>,
+    /// 按 realm、service 和 scope 缓存的 bearer token
+    tokens: Mutex<HashMap<String, String>>,
+}
+
+impl Default for RegistryClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RegistryClient {
+    pub fn new() -> Self {
+        Self {
+            http_client: reqwest::Client::new(),
+            credentials: None,
+            tokens: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 使用用户名和密码认证，basic 挑战时直接使用，bearer 挑战时用于获取 token
+    pub fn with_credentials(mut self, username: &str, password: &str) -> Self {
+        self.credentials = Some((username.to_string(), password.to_string()));
+        self
+    }
+
+    /// 获取 manifest，registry 为 docker.io 时访问 registry-1.docker.io
+    pub async fn get_manifest(
+        &self,
+        registry: &str,
+        repository: &str,
+        reference: &str,
+    ) -> anyhow::Result<Manifest> {
+        let url = format!(
+            "{}/v2/{repository}/manifests/{reference}",
+            registry_url(registry)
+        );
+        let resp = self
+            .execute(|client| client.get(&url).header(header::ACCEPT, MANIFEST_ACCEPT))
+            .await?;
+
+        let status = resp.status();
+        if !status.is_success() {
+            let text = resp.text().await?;
+            anyhow::bail!("get manifest {repository}:{reference} failed: {status} {text}");
+        }
+
+        let digest = header_str(&resp, DOCKER_CONTENT_DIGEST);
+        let media_type = content_type(&resp);
+        let body = resp.bytes().await?.to_vec();
+        let digest = digest.unwrap_or_else(|| format!("sha256:{}", sha256_hex(&body)));
+
+        Ok(Manifest {
+            digest,
+            media_type,
+            body,
+        })
+    }
+
+    /// 通过 HEAD 请求获取 manifest 的 digest、类型和大小，不存在时返回 None
+    pub async fn head_manifest(
+        &self,
+        registry: &str,
+        repository: &str,
+        reference: &str,
+    ) -> anyhow::Result<Option<Descriptor>> {
+        let url = format!(
+            "{}/v2/{repository}/manifests/{reference}",
+            registry_url(registry)
+        );
+        let resp = self
+            .execute(|client| client.head(&url).header(header::ACCEPT, MANIFEST_ACCEPT))
+            .await?;
+
+        let status = resp.status();
+        if status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            anyhow::bail!("head manifest {repository}:{reference} failed: {status}");
+        }
+        let digest = header_str(&resp, DOCKER_CONTENT_DIGEST).with_context(|| {
+            format!("registry did not return the digest of {repository}:{reference}")
+        })?;
+        Ok(Some(Descriptor {
+            media_type: content_type(&resp),
+            digest,
+            // HEAD 响应没有响应体，content_length() 总是 0，需要直接读取头
+            size: header_str(&resp, header::CONTENT_LENGTH.as_str())
+                .and_then(|len| len.parse().ok())
+                .unwrap_or_default(),
+            platform: None,
+            annotations: None,
+        }))
+    }
+
+    /// 列出仓库的所有标签，按 `Link` 头翻页
+    pub async fn list_tags(&self, registry: &str, repository: &str) -> anyhow::Result<Vec<String>> {
+        let base = registry_url(registry);
+        let mut url = format!("{base}/v2/{repository}/tags/list?n={TAGS_PAGE_SIZE}");
+        let mut tags = Vec::new();
+        loop {
+            let resp = self.execute(|client| client.get(&url)).await?;
+            let status = resp.status();
+            if !status.is_success() {
+                let text = resp.text().await?;
+                anyhow::bail!("list tags of {repository} failed: {status} {text}");
+            }
+
+            let next = header_str(&resp, header::LINK.as_str()).and_then(|link| next_link(&link));
+            let page: TagList = resp.json().await?;
+            tags.extend(page.tags.unwrap_or_default());
+
+            match next {
+                // Link 中的地址通常是相对路径
+                Some(next) if next.starts_with('/') => url = format!("{base}{next}"),
+                Some(next) => url = next,
+                None => return Ok(tags),
+            }
+        }
+    }
+
+    /// 获取 blob 的响应，调用方可以流式读取，registry 重定向到存储地址时自动跟随
+    pub async fn blob_response(
+        &self,
+        registry: &str,
+        repository: &str,
+        digest: &str,
+    ) -> anyhow::Result<Response> {
+        self.blob_response_from(registry, repository, digest, 0).await
+    }
+
+    /// 从 offset 开始获取 blob，用于续传
+    /// 返回 206 时内容从 offset 开始，registry 不支持 Range 时返回 200 和完整内容
+    pub async fn blob_response_from(
+        &self,
+        registry: &str,
+        repository: &str,
+        digest: &str,
+        offset: u64,
+    ) -> anyhow::Result<Response> {
+        let url = format!("{}/v2/{repository}/blobs/{digest}", registry_url(registry));
+        let resp = self
+            .execute(|client| match offset {
+                0 => client.get(&url),
+                offset => client.get(&url).header(header::RANGE, format!("bytes={offset}-")),
+            })
+            .await?;
+        let status = resp.status();
+        if !status.is_success() {
+            anyhow::bail!("get blob {repository}@{digest} failed: {status}");
+        }
+        Ok(resp)
+    }
+
+    /// 获取完整的 blob，sha256 digest 会校验内容
+    pub async fn get_blob(
+        &self,
+        registry: &str,
+        repository: &str,
+        digest: &str,
+    ) -> anyhow::Result<Vec<u8>> {
+        let body = self
+            .blob_response(registry, repository, digest)
+            .await?
+            .bytes()
+            .await?
+            .to_vec();
+        if let Some(expected) = digest.strip_prefix("sha256:") {
+            let actual = sha256_hex(&body);
+            if actual != expected {
+                anyhow::bail!(
+                    "blob {digest} of {repository} does not match its digest, got sha256:{actual}"
+                );
+            }
+        }
+        Ok(body)
+    }
+
+    /// 目标仓库中是否已经有该 blob
+    pub async fn blob_exists(
+        &self,
+        registry: &str,
+        repository: &str,
+        digest: &str,
+    ) -> anyhow::Result<bool> {
+        let url = format!("{}/v2/{repository}/blobs/{digest}", registry_url(registry));
+        let resp = self.execute(|client| client.head(&url)).await?;
+        match resp.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status => anyhow::bail!("head blob {repository}@{digest} failed: {status}"),
+        }
+    }
+
+    /// 开始上传 blob，指定 mount 时先尝试从同一 registry 的其他仓库挂载，挂载成功时不需要上传
+    pub async fn start_upload(
+        &self,
+        registry: &str,
+        repository: &str,
+        mount: Option<(&str, &str)>,
+    ) -> anyhow::Result<Upload> {
+        let base = registry_url(registry);
+        let url = match mount {
+            Some((digest, from)) => {
+                format!("{base}/v2/{repository}/blobs/uploads/?mount={digest}&from={from}")
+            }
+            None => format!("{base}/v2/{repository}/blobs/uploads/"),
+        };
+        let resp = self
+            .execute(|client| client.post(&url).header(header::CONTENT_LENGTH, 0))
+            .await?;
+        let status = resp.status();
+        if status == StatusCode::CREATED && mount.is_some() {
+            return Ok(Upload::Mounted);
+        }
+        if status != StatusCode::ACCEPTED {
+            let text = resp.text().await?;
+            anyhow::bail!("start blob upload to {repository} failed: {status} {text}");
+        }
+        let location = upload_location(&base, &resp)?;
+        Ok(Upload::Session(BlobUpload {
+            location,
+            offset: 0,
+        }))
+    }
+
+    /// 以 PATCH 上传一段 blob 内容，registry 返回下一段的上传地址
+    pub async fn upload_chunk(&self, upload: &mut BlobUpload, chunk: Vec<u8>) -> anyhow::Result<()> {
+        if chunk.is_empty() {
+            return Ok(());
+        }
+        let end = upload.offset + chunk.len() as u64 - 1;
+        let range = format!("{}-{end}", upload.offset);
+        let resp = self
+            .execute(|client| {
+                client
+                    .patch(&upload.location)
+                    .header(header::CONTENT_TYPE, "application/octet-stream")
+                    .header(header::CONTENT_RANGE, &range)
+                    .body(chunk.clone())
+            })
+            .await?;
+        let status = resp.status();
+        if status != StatusCode::ACCEPTED {
+            let text = resp.text().await?;
+            anyhow::bail!("upload blob chunk {range} failed: {status} {text}");
+        }
+        upload.location = upload_location(&upload.base(), &resp)?;
+        upload.offset = end + 1;
+        Ok(())
+    }
+
+    /// 以 PUT 结束上传，registry 校验整个 blob 的 digest
+    pub async fn finish_upload(&self, upload: BlobUpload, digest: &str) -> anyhow::Result<()> {
+        let separator = if upload.location.contains('?') { '&' } else { '?' };
+        let url = format!("{}{separator}digest={digest}", upload.location);
+        let resp = self
+            .execute(|client| client.put(&url).header(header::CONTENT_LENGTH, 0))
+            .await?;
+        let status = resp.status();
+        if status != StatusCode::CREATED {
+            let text = resp.text().await?;
+            anyhow::bail!("finish blob upload {digest} failed: {status} {text}");
+        }
+        Ok(())
+    }
+
+    /// 取消上传会话，registry 丢弃已上传的内容
+    pub async fn cancel_upload(&self, upload: BlobUpload) -> anyhow::Result<()> {
+        let resp = self
+            .execute(|client| client.delete(&upload.location))
+            .await?;
+        let status = resp.status();
+        if !status.is_success() && status != StatusCode::NOT_FOUND {
+            let text = resp.text().await?;
+            anyhow::bail!("cancel blob upload failed: {status} {text}");
+        }
+        Ok(())
+    }
+
+    /// 推送 manifest，reference 为标签或 digest，返回 registry 计算的 digest
+    pub async fn put_manifest(
+        &self,
+        registry: &str,
+        repository: &str,
+        reference: &str,
+        manifest: &Manifest,
+    ) -> anyhow::Result<String> {
+        let url = format!("{}/v2/{repository}/manifests/{reference}", registry_url(registry));
+        let resp = self
+            .execute(|client| {
+                client
+                    .put(&url)
+                    .header(header::CONTENT_TYPE, &manifest.media_type)
+                    .body(manifest.body.clone())
+            })
+            .await?;
+        let status = resp.status();
+        if !status.is_success() {
+            let text = resp.text().await?;
+            anyhow::bail!("put manifest {repository}:{reference} failed: {status} {text}");
+        }
+        Ok(header_str(&resp, DOCKER_CONTENT_DIGEST)
+            .unwrap_or_else(|| format!("sha256:{}", sha256_hex(&manifest.body))))
+    }
+
+    /// 发送请求，返回 401 时按 `WWW-Authenticate` 认证后重试一次
+    async fn execute(
+        &self,
+        build: impl Fn(&reqwest::Client) -> RequestBuilder,
+    ) -> anyhow::Result<Response> {
+        let request = build(&self.http_client);
+        let cache_key = request
+            .try_clone()
+            .and_then(|r| r.build().ok())
+            .map(|r| token_cache_key(r.url()));
+        let cached = cache_key
+            .as_ref()
+            .and_then(|key| self.tokens.lock().unwrap().get(key).cloned());
+        let resp = match &cached {
+            Some(token) => request.bearer_auth(token).send().await?,
+            None => request.send().await?,
+        };
+        if resp.status() != StatusCode::UNAUTHORIZED {
+            return Ok(resp);
+        }
+
+        let challenge = header_str(&resp, header::WWW_AUTHENTICATE.as_str())
+            .context("registry requires auth but sent no challenge")?;
+        let request = build(&self.http_client);
+        match parse_challenge(&challenge) {
+            Some((scheme, params)) if scheme.eq_ignore_ascii_case("bearer") => {
+                let token = self.fetch_token(&params).await?;
+                if let Some(key) = cache_key {
+                    self.tokens.lock().unwrap().insert(key, token.clone());
+                }
+                Ok(request.bearer_auth(token).send().await?)
+            }
+            Some((scheme, _)) if scheme.eq_ignore_ascii_case("basic") => {
+                let (username, password) = self
+                    .credentials
+                    .as_ref()
+                    .context("registry requires basic auth but no credentials are configured")?;
+                Ok(request.basic_auth(username, Some(password)).send().await?)
+            }
+            _ => anyhow::bail!("unsupported auth challenge: {challenge}"),
+        }
+    }
+
+    /// 按 Bearer 挑战获取 token，配置了凭证时使用 basic auth，否则匿名获取
+    async fn fetch_token(&self, params: &HashMap<String, String>) -> anyhow::Result<String> {
+        let realm = params.get("realm").context("auth challenge has no realm")?;
+        let query = params
+            .iter()
+            .filter(|(k, _)| k.as_str() != "realm")
+            .collect::<Vec<_>>();
+        let mut req = self.http_client.get(realm).query(&query);
+        if let Some((username, password)) = &self.credentials {
+            req = req.basic_auth(username, Some(password));
+        }
+        let resp = req.send().await?;
+        let status = resp.status();
+        if !status.is_success() {
+            anyhow::bail!("fetch registry token failed: {status}");
+        }
+        let token: TokenResponse = resp.json().await?;
+        token
+            .token
+            .or(token.access_token)
+            .context("registry token response has no token")
+    }
+}
+
+/// docker hub 使用 registry-1.docker.io，本机的 registry 使用 http
+fn registry_url(registry: &str) -> String {
+    match registry {
+        "docker.io" | "index.docker.io" => "https://registry-1.docker.io".to_string(),
+        _ if is_loopback(registry) => format!("http://{registry}"),
+        _ => format!("https://{registry}"),
+    }
+}
+
+fn is_loopback(registry: &str) -> bool {
+    let host = registry.rsplit_once(':').map_or(registry, |(host, _)| host);
+    matches!(host, "localhost" | "127.0.0.1" | "[::1]")
+}
+
+/// 同一仓库的请求共用 token，token 的 scope 按仓库区分
+fn token_cache_key(url: &reqwest::Url) -> String {
+    let path = url.path();
+    let repository = path
+        .strip_prefix("/v2/")
+        .and_then(|p| {
+            ["/manifests/", "/blobs/", "/tags/"]
+                .iter()
+                .find_map(|s| p.split_once(s))
+        })
+        .map_or("", |(repository, _)| repository);
+    format!("{}/{repository}", url.host_str().unwrap_or_default())
+}
+
+fn header_str(resp: &Response, name: &str) -> Option<String> {
+    resp.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+/// 上传接口返回的 Location，可能是相对地址
+fn upload_location(base: &str, resp: &Response) -> anyhow::Result<String> {
+    let location = header_str(resp, header::LOCATION.as_str())
+        .context("registry did not return the upload location")?;
+    if location.starts_with('/') {
+        Ok(format!("{base}{location}"))
+    } else {
+        Ok(location)
+    }
+}
+
+/// Content-Type 去掉 charset 等参数
+fn content_type(resp: &Response) -> String {
+    header_str(resp, header::CONTENT_TYPE.as_str())
+        .map(|v| v.split(';').next().unwrap_or_default().trim().to_string())
+        .unwrap_or_default()
+}
+
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    hex::encode(Sha256::digest(data))
+}
+
+/// 解析 `<url>; rel="next"` 形式的 Link 头，返回下一页的地址
+fn next_link(link: &str) -> Option<String> {
+    link.split(',').find_map(|part| {
+        let (url, params) = part.split_once(';')?;
+        let is_next = params
+            .split(';')
+            .any(|p| p.trim().replace(' ', "") == r#"rel="next""# || p.trim() == "rel=next");
+        is_next.then(|| {
+            url.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        })
+    })
+}
+
+/// 解析 `Bearer realm="...",service="...",scope="..."`，返回认证方式和参数
+fn parse_challenge(challenge: &str) -> Option<(String, HashMap<String, String>)> {
+    let (scheme, rest) = challenge
+        .trim()
+        .split_once(' ')
+        .unwrap_or((challenge.trim(), ""));
+
+    let mut params = HashMap::new();
+    let mut rest = rest.trim();
+    while !rest.is_empty() {
+        let (key, value) = rest.split_once('=')?;
+        let key = key.trim().trim_start_matches(',').trim().to_lowercase();
+        let value = value.trim_start();
+        let (value, remain) = if let Some(quoted) = value.strip_prefix('"') {
+            let end = quoted.find('"')?;
+            (&quoted[..end], &quoted[end + 1..])
+        } else {
+            let end = value.find(',').unwrap_or(value.len());
+            (&value[..end], &value[end..])
+        };
+        params.insert(key, value.to_string());
+        rest = remain.trim_start().trim_start_matches(',').trim_start();
+    }
+    Some((scheme.to_string(), params))
+}
+
+#[cfg(test)]
+pub(crate) mod mock;
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::{mock::MockRegistry, *};
+
+    #[test]
+    fn test_parse_challenge() {
+        let (scheme, params) = parse_challenge(
+            r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/nginx:pull""#,
+        )
+        .unwrap();
+        assert_eq!(scheme, "Bearer");
+        assert_eq!(params["realm"], "https://auth.docker.io/token");
+        assert_eq!(params["service"], "registry.docker.io");
+        assert_eq!(params["scope"], "repository:library/nginx:pull");
+
+        let (scheme, params) = parse_challenge(r#"Basic realm="x""#).unwrap();
+        assert_eq!((scheme.as_str(), params["realm"].as_str()), ("Basic", "x"));
+    }
+
+    #[test]
+    fn test_next_link() {
+        assert_eq!(
+            next_link(r#"</v2/app/tags/list?n=2&last=b>; rel="next""#).as_deref(),
+            Some("/v2/app/tags/list?n=2&last=b")
+        );
+        assert_eq!(
+            next_link(r#"</v2/app/tags/list?n=2&last=b>; rel="prev""#),
+            None
+        );
+    }
+
+    #[test]
+    fn test_platform_digest() {
+        let manifest = Manifest {
+            digest: "sha256:index".to_string(),
+            media_type: "application/vnd.oci.image.index.v1+json".to_string(),
+            body: br#"{"manifests":[
+                {"digest":"sha256:arm","platform":{"architecture":"arm64","os":"linux"}},
+                {"digest":"sha256:amd","platform":{"architecture":"amd64","os":"linux"}}
+            ]}"#
+            .to_vec(),
+        };
+        assert!(manifest.is_index());
+        assert_eq!(
+            manifest
+                .platform_digest(&"linux/amd64".parse().unwrap())
+                .as_deref(),
+            Some("sha256:amd")
+        );
+        assert_eq!(
+            manifest.platform_digest(&"windows/amd64".parse().unwrap()),
+            None
+        );
+    }
+
+    const USER: &str = "alice";
+    const PASSWORD: &str = "secret";
+
+    /// 需要 bearer token 的 registry，team/app 中有 v1 到 v5 五个标签指向同一个镜像
+    async fn mock_registry() -> (Arc<MockRegistry>, String, Vec<u8>) {
+        let registry = Arc::new(MockRegistry::with_credentials(USER, PASSWORD));
+        let blob = b"layer content".to_vec();
+        let manifest = serde_json::to_vec(&serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": OCI_MANIFEST,
+            "config": { "mediaType": "application/vnd.oci.image.config.v1+json", "digest": "sha256:cfg", "size": 2 },
+            "layers": [mock::descriptor(&blob)],
+        }))
+        .unwrap();
+        registry.add_blob("team/app", &blob);
+        for i in 1..=5 {
+            registry.add_manifest("team/app", &format!("v{i}"), OCI_MANIFEST, &manifest);
+        }
+        let addr = registry.serve().await;
+        (registry, addr, blob)
+    }
+
+    #[tokio::test]
+    async fn test_registry_client() {
+        let (registry, addr, layer) = mock_registry().await;
+        let client = RegistryClient::new().with_credentials(USER, PASSWORD);
+
+        let manifest = client
+            .get_manifest(&addr, "team/app", "v1")
+            .await
+            .unwrap();
+        assert_eq!(manifest.media_type, OCI_MANIFEST);
+        let image = manifest.image_manifest().unwrap();
+        assert_eq!(image.layers.len(), 1);
+        assert!(manifest.index().is_err());
+
+        let head = client
+            .head_manifest(&addr, "team/app", "v1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(head.digest, manifest.digest);
+        assert_eq!(head.size, manifest.body.len() as u64);
+
+        let tags = client.list_tags(&addr, "team/app").await.unwrap();
+        assert_eq!(tags, ["v1", "v2", "v3", "v4", "v5"]);
+
+        let blob = client
+            .get_blob(&addr, "team/app", &image.layers[0].digest)
+            .await
+            .unwrap();
+        assert_eq!(blob, layer);
+        assert!(client
+            .get_blob(
+                &addr,
+                "team/app",
+                &format!("sha256:{}", "0".repeat(64))
+            )
+            .await
+            .is_err());
+
+        // 同一仓库的 token 只获取一次
+        assert_eq!(*registry.token_requests.lock().unwrap(), 1);
+
+        // 没有凭证时 token 接口拒绝
+        let anonymous = RegistryClient::new();
+        assert!(anonymous
+            .get_manifest(&addr, "team/app", "v1")
+            .await
+            .is_err());
+    }
+}
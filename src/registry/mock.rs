@@ -0,0 +1,298 @@
+//! 测试用的 registry:2 替身，registry、copy、cache 和 inspect 的测试共用
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Router,
+};
+
+use super::{sha256_hex, Descriptor, DOCKER_CONTENT_DIGEST};
+
+/// (仓库, 标签或 digest)
+pub(crate) type Key = (String, String);
+
+/// 设置 credentials 时要求 bearer token，token 接口要求这组 basic 凭证，否则不需要认证
+/// 仓库名可以包含 `/`，标签列表每页两个
+#[derive(Default)]
+pub(crate) struct MockRegistry {
+    pub credentials: Option<(String, String)>,
+    /// 仓库中 digest 对应的内容
+    pub blobs: Mutex<HashMap<Key, Vec<u8>>>,
+    /// 仓库中标签或 digest 对应的类型和内容
+    pub manifests: Mutex<HashMap<Key, (String, Vec<u8>)>>,
+    /// 未完成也未取消的上传会话
+    pub uploads: Mutex<HashMap<String, Vec<u8>>>,
+    /// 已创建的上传会话数，用作会话 id
+    pub sessions: Mutex<usize>,
+    pub patches: Mutex<usize>,
+    /// 每次 GET blob 请求的 Range 头
+    pub ranges: Mutex<Vec<Option<String>>>,
+    /// 接下来这么多次 Range 请求返回从头开始的 206，模拟有问题的 registry
+    pub misaligned_ranges: Mutex<usize>,
+    pub token_requests: Mutex<usize>,
+}
+
+pub(crate) const TOKEN: &str = "registry-token";
+
+impl MockRegistry {
+    pub fn with_credentials(username: &str, password: &str) -> Self {
+        Self {
+            credentials: Some((username.to_string(), password.to_string())),
+            ..Default::default()
+        }
+    }
+
+    /// 把 blob 放入仓库，返回其 digest
+    pub fn add_blob(&self, repository: &str, blob: &[u8]) -> String {
+        let digest = format!("sha256:{}", sha256_hex(blob));
+        self.blobs
+            .lock()
+            .unwrap()
+            .insert((repository.to_string(), digest.clone()), blob.to_vec());
+        digest
+    }
+
+    /// 把 manifest 以 reference 和 digest 放入仓库，返回其 digest
+    pub fn add_manifest(&self, repository: &str, reference: &str, media_type: &str, body: &[u8]) -> String {
+        let digest = format!("sha256:{}", sha256_hex(body));
+        let mut manifests = self.manifests.lock().unwrap();
+        for reference in [reference, digest.as_str()] {
+            manifests.insert(
+                (repository.to_string(), reference.to_string()),
+                (media_type.to_string(), body.to_vec()),
+            );
+        }
+        digest
+    }
+
+    /// 在随机端口上启动，返回 `127.0.0.1:port`
+    pub async fn serve(self: &Arc<Self>) -> String {
+        let app = Router::new().fallback(handle).with_state(self.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        addr
+    }
+
+    fn authorized(&self, headers: &HeaderMap) -> bool {
+        self.credentials.is_none()
+            || headers.get(header::AUTHORIZATION).and_then(|v| v.to_str().ok())
+                == Some(format!("Bearer {TOKEN}").as_str())
+    }
+
+    fn token(&self, headers: &HeaderMap) -> Response {
+        *self.token_requests.lock().unwrap() += 1;
+        let Some((username, password)) = &self.credentials else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        let basic = format!(
+            "Basic {}",
+            base64::Engine::encode(
+                &base64::engine::general_purpose::STANDARD,
+                format!("{username}:{password}")
+            )
+        );
+        if headers.get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()) != Some(basic.as_str()) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        axum::Json(serde_json::json!({ "token": TOKEN })).into_response()
+    }
+
+    fn tags(&self, repository: &str, query: &HashMap<String, String>) -> Response {
+        let mut tags = self
+            .manifests
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|(repo, reference)| repo == repository && !reference.contains(':'))
+            .map(|(_, tag)| tag.clone())
+            .collect::<Vec<_>>();
+        tags.sort();
+        // 每页两个标签，用 last 翻页
+        let n = 2;
+        let start = query
+            .get("last")
+            .and_then(|last| tags.iter().position(|t| t == last))
+            .map_or(0, |i| i + 1);
+        let page = tags.iter().skip(start).take(n).cloned().collect::<Vec<_>>();
+        let body = axum::Json(serde_json::json!({ "name": repository, "tags": page }));
+        match page.last() {
+            Some(last) if start + n < tags.len() => {
+                let link = format!(r#"</v2/{repository}/tags/list?n={n}&last={last}>; rel="next""#);
+                ([(header::LINK, link)], body).into_response()
+            }
+            _ => body.into_response(),
+        }
+    }
+
+    fn blob(&self, key: Key, method: &Method, headers: &HeaderMap) -> Response {
+        let Some(blob) = self.blobs.lock().unwrap().get(&key).cloned() else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        if method == Method::HEAD {
+            return blob.into_response();
+        }
+        let range = headers.get(header::RANGE).map(|v| v.to_str().unwrap().to_string());
+        self.ranges.lock().unwrap().push(range.clone());
+        match range.and_then(|r| r.strip_prefix("bytes=")?.strip_suffix('-')?.parse::<usize>().ok()) {
+            Some(_) if take_one(&self.misaligned_ranges) => (
+                StatusCode::PARTIAL_CONTENT,
+                [(header::CONTENT_RANGE, format!("bytes 0-{}/{}", blob.len() - 1, blob.len()))],
+                blob,
+            )
+                .into_response(),
+            Some(start) => (
+                StatusCode::PARTIAL_CONTENT,
+                [(header::CONTENT_RANGE, format!("bytes {start}-{}/{}", blob.len() - 1, blob.len()))],
+                blob[start..].to_vec(),
+            )
+                .into_response(),
+            None => blob.into_response(),
+        }
+    }
+
+    /// 同时指定 mount 和 from 且源仓库中有该 blob 时挂载，否则打开上传会话
+    fn start_upload(&self, repository: &str, query: &HashMap<String, String>) -> Response {
+        if let (Some(digest), Some(from)) = (query.get("mount"), query.get("from")) {
+            let mut blobs = self.blobs.lock().unwrap();
+            if let Some(blob) = blobs.get(&(from.clone(), digest.clone())).cloned() {
+                blobs.insert((repository.to_string(), digest.clone()), blob);
+                return StatusCode::CREATED.into_response();
+            }
+        }
+        let id = {
+            let mut sessions = self.sessions.lock().unwrap();
+            *sessions += 1;
+            sessions.to_string()
+        };
+        self.uploads.lock().unwrap().insert(id.clone(), Vec::new());
+        let location = format!("/v2/{repository}/blobs/uploads/{id}");
+        (StatusCode::ACCEPTED, [(header::LOCATION, location)]).into_response()
+    }
+
+    fn upload(&self, repository: &str, id: &str, method: &Method, query: &HashMap<String, String>, body: Bytes) -> Response {
+        let mut uploads = self.uploads.lock().unwrap();
+        let Some(upload) = uploads.get_mut(id) else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        if method == Method::DELETE {
+            uploads.remove(id);
+            return StatusCode::NO_CONTENT.into_response();
+        }
+        upload.extend_from_slice(&body);
+        match *method {
+            Method::PATCH => {
+                *self.patches.lock().unwrap() += 1;
+                let location = format!("/v2/{repository}/blobs/uploads/{id}");
+                (StatusCode::ACCEPTED, [(header::LOCATION, location)]).into_response()
+            }
+            Method::PUT => {
+                let blob = uploads.remove(id).unwrap();
+                let digest = &query["digest"];
+                if format!("sha256:{}", sha256_hex(&blob)) != *digest {
+                    return StatusCode::BAD_REQUEST.into_response();
+                }
+                self.blobs
+                    .lock()
+                    .unwrap()
+                    .insert((repository.to_string(), digest.clone()), blob);
+                StatusCode::CREATED.into_response()
+            }
+            _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+        }
+    }
+
+    fn manifest(&self, key: Key, method: &Method, headers: &HeaderMap, body: Bytes) -> Response {
+        if method == Method::PUT {
+            let media_type = headers[header::CONTENT_TYPE].to_str().unwrap();
+            let digest = self.add_manifest(&key.0, &key.1, media_type, &body);
+            return (StatusCode::CREATED, [(DOCKER_CONTENT_DIGEST, digest)]).into_response();
+        }
+        match self.manifests.lock().unwrap().get(&key) {
+            Some((media_type, body)) => (
+                [
+                    (header::CONTENT_TYPE.as_str(), media_type.clone()),
+                    (DOCKER_CONTENT_DIGEST, format!("sha256:{}", sha256_hex(body))),
+                ],
+                body.clone(),
+            )
+                .into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+}
+
+/// 计数大于 0 时减一并返回 true
+fn take_one(counter: &Mutex<usize>) -> bool {
+    let mut counter = counter.lock().unwrap();
+    if *counter == 0 {
+        return false;
+    }
+    *counter -= 1;
+    true
+}
+
+/// 按路径分发请求，HEAD 与 GET 相同，由 hyper 去掉响应体
+async fn handle(
+    State(registry): State<Arc<MockRegistry>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let query = url::form_urlencoded::parse(uri.query().unwrap_or_default().as_bytes())
+        .into_owned()
+        .collect::<HashMap<_, _>>();
+    let path = uri.path();
+    if path == "/token" {
+        return registry.token(&headers);
+    }
+    let Some(path) = path.strip_prefix("/v2/") else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let repository = ["/tags/list", "/blobs/", "/manifests/"]
+        .iter()
+        .find_map(|marker| path.rsplit_once(marker).map(|(repo, _)| repo))
+        .unwrap_or_default();
+    if !registry.authorized(&headers) {
+        let host = headers[header::HOST].to_str().unwrap();
+        let challenge = format!(
+            r#"Bearer realm="http://{host}/token",service="mock",scope="repository:{repository}:pull""#
+        );
+        return (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, challenge)]).into_response();
+    }
+
+    if path.ends_with("/tags/list") {
+        registry.tags(repository, &query)
+    } else if let Some((repo, id)) = path.rsplit_once("/blobs/uploads/") {
+        match id {
+            "" => registry.start_upload(repo, &query),
+            id => registry.upload(repo, id, &method, &query, body),
+        }
+    } else if let Some((repo, digest)) = path.rsplit_once("/blobs/") {
+        registry.blob((repo.to_string(), digest.to_string()), &method, &headers)
+    } else if let Some((repo, reference)) = path.rsplit_once("/manifests/") {
+        registry.manifest((repo.to_string(), reference.to_string()), &method, &headers, body)
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+/// blob 的描述，类型为 `application/octet-stream`
+pub(crate) fn descriptor(blob: &[u8]) -> Descriptor {
+    Descriptor {
+        media_type: "application/octet-stream".to_string(),
+        digest: format!("sha256:{}", sha256_hex(blob)),
+        size: blob.len() as u64,
+        platform: None,
+        annotations: None,
+    }
+}
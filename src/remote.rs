@@ -0,0 +1,274 @@
+/// 默认的 github 地址
+pub const DEFAULT_GITHUB_HOST: &str = "github.com";
+
+/// 仓库地址中解析出的主机和 owner/repo，只写 owner/repo 时没有主机
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoLocation {
+    pub host: Option<String>,
+    /// http(s) 地址中的端口，ssh 地址中的端口不是网页的端口，不保留
+    pub port: Option<u16>,
+    /// 地址为 http 时网页和 api 也使用 http
+    pub insecure: bool,
+    pub owner: String,
+    pub repo: String,
+}
+
+impl RepoLocation {
+    /// 解析仓库地址，支持:
+    /// - owner/repo
+    /// - http(s)://host(:port)/owner/repo(.git)
+    /// - ssh://git@host(:port)/owner/repo.git
+    /// - git@host:owner/repo.git
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        Self::parse_path(input, false)
+    }
+
+    /// 和 parse 相同，但 owner 可以包含多级路径，如 gitlab 子群组中的 `group/sub/repo`
+    pub fn parse_nested(input: &str) -> anyhow::Result<Self> {
+        Self::parse_path(input, true)
+    }
+
+    fn parse_path(input: &str, nested: bool) -> anyhow::Result<Self> {
+        let input = input.trim().trim_end_matches('/');
+
+        let mut port = None;
+        let mut insecure = false;
+        let (host, path) = if let Some((scheme, rest)) = input.split_once("://") {
+            if !matches!(scheme, "https" | "http" | "ssh" | "git") {
+                anyhow::bail!("unsupported scheme `{scheme}` in repository `{input}`");
+            }
+            let (authority, path) = rest
+                .split_once('/')
+                .ok_or_else(|| anyhow::anyhow!("repository `{input}` has no owner/repo path"))?;
+            // 去掉 user@，http(s) 地址保留端口
+            let authority = authority.rsplit('@').next().unwrap_or(authority);
+            let (host, authority_port) = match authority.split_once(':') {
+                Some((host, port)) => {
+                    let port = port
+                        .parse::<u16>()
+                        .map_err(|_| anyhow::anyhow!("port `{port}` in repository `{input}` is not valid"))?;
+                    (host, Some(port))
+                }
+                None => (authority, None),
+            };
+            if matches!(scheme, "https" | "http") {
+                port = authority_port;
+                insecure = scheme == "http";
+            }
+            (Some(host.to_string()), path)
+        } else if let Some((user_host, path)) = input.split_once(':') {
+            // scp 形式 git@host:owner/repo.git
+            let host = user_host.rsplit('@').next().unwrap_or(user_host);
+            (Some(host.to_string()), path)
+        } else {
+            (None, input)
+        };
+
+        let path = path.trim_matches('/').trim_end_matches(".git");
+        let parts = path.split('/').collect::<Vec<_>>();
+        let valid = parts.len() == 2 || (nested && parts.len() > 2);
+        match parts.split_last() {
+            Some((repo, owner)) if valid && parts.iter().all(|p| !p.is_empty()) => Ok(Self {
+                host: host.filter(|h| !h.is_empty()),
+                port,
+                insecure,
+                owner: owner.join("/"),
+                repo: repo.to_string(),
+            }),
+            _ => anyhow::bail!("pusher is not valid: `{input}`, expected owner/repo or a repository url"),
+        }
+    }
+
+    /// 仓库所在的主机，没有写主机时使用配置的主机
+    pub fn host_or<'a>(&'a self, default: &'a str) -> &'a str {
+        self.host.as_deref().unwrap_or(default)
+    }
+
+    /// 仓库所在实例的网页地址，保留地址中的 http 和端口，没有写主机时为 None
+    pub fn web_base(&self) -> Option<String> {
+        let host = self.host.as_deref()?;
+        let scheme = if self.insecure { "http" } else { "https" };
+        Some(match self.port {
+            Some(port) => format!("{scheme}://{host}:{port}"),
+            None => format!("{scheme}://{host}"),
+        })
+    }
+
+    /// 仓库所在 github 的地址，带有端口或为 http 时 api 地址为网页地址加上 `/api/v3`
+    pub fn github_urls(&self) -> Option<GithubUrls> {
+        let host = self.host.as_deref()?;
+        let web = self.web_base()?;
+        if web == web_base(host) {
+            return Some(GithubUrls::from_host(host));
+        }
+        Some(GithubUrls {
+            host: host.to_string(),
+            api: format!("{web}/api/v3"),
+            web,
+        })
+    }
+}
+
+/// github api 地址，github.com 为 api.github.com，Enterprise Server 为 `https://host/api/v3`
+pub fn api_base(host: &str) -> String {
+    if host == DEFAULT_GITHUB_HOST {
+        "https://api.github.com".to_string()
+    } else {
+        format!("https://{host}/api/v3")
+    }
+}
+
+/// github 网页地址
+pub fn web_base(host: &str) -> String {
+    format!("https://{host}")
+}
+
+/// github 实例的主机名、api 地址和网页地址
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GithubUrls {
+    pub host: String,
+    pub api: String,
+    pub web: String,
+}
+
+impl GithubUrls {
+    /// 由主机名得到默认的 https 地址
+    pub fn from_host(host: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            api: api_base(host),
+            web: web_base(host),
+        }
+    }
+
+    /// 优先使用配置中的完整地址，支持 http 和带路径的代理地址，空字符串表示未配置
+    /// 只配置了其中一个时由它推导另一个: 网页地址加上 `/api/v3` 为 api 地址，反之去掉 `/api/v3`
+    pub fn resolve(host: &str, api: &str, web: &str) -> Self {
+        let api = api.trim().trim_end_matches('/');
+        let web = web.trim().trim_end_matches('/');
+        let default = Self::from_host(host);
+        let (api, web) = match (api, web) {
+            ("", "") => return default,
+            (api, "") => {
+                let web = if api == default.api {
+                    default.web
+                } else {
+                    api.strip_suffix("/api/v3").map_or(default.web, |web| web.to_string())
+                };
+                (api.to_string(), web)
+            }
+            ("", web) => {
+                let api = if web == default.web {
+                    default.api
+                } else {
+                    format!("{web}/api/v3")
+                };
+                (api, web.to_string())
+            }
+            (api, web) => (api.to_string(), web.to_string()),
+        };
+        Self {
+            host: host.to_string(),
+            api,
+            web,
+        }
+    }
+}
+
+/// 地址中的主机名，不含用户名和端口
+pub fn url_host(url: &str) -> Option<&str> {
+    let (_, rest) = url.trim().split_once("://")?;
+    let authority = rest.split('/').next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = host.split(':').next()?;
+    Some(host).filter(|h| !h.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(input: &str) -> (Option<String>, String, String) {
+        let l = RepoLocation::parse(input).unwrap();
+        (l.host, l.owner, l.repo)
+    }
+
+    #[test]
+    fn test_parse_repo_location() {
+        let expected = |host: Option<&str>| {
+            (host.map(|h| h.to_string()), "abc".to_string(), "docker_image_pusher".to_string())
+        };
+        assert_eq!(parse("abc/docker_image_pusher"), expected(None));
+        assert_eq!(parse("https://github.com/abc/docker_image_pusher/"), expected(Some("github.com")));
+        assert_eq!(parse("https://ghe.example.com/abc/docker_image_pusher.git"), expected(Some("ghe.example.com")));
+        assert_eq!(parse("ssh://git@ghe.example.com:2222/abc/docker_image_pusher.git"), expected(Some("ghe.example.com")));
+        assert_eq!(parse("git@ghe.example.com:abc/docker_image_pusher.git"), expected(Some("ghe.example.com")));
+
+        assert!(RepoLocation::parse("abc").is_err());
+        assert!(RepoLocation::parse("https://github.com/abc").is_err());
+        assert!(RepoLocation::parse("ftp://github.com/abc/def").is_err());
+        assert!(RepoLocation::parse("group/sub/repo").is_err());
+    }
+
+    #[test]
+    fn test_parse_nested() {
+        let l = RepoLocation::parse_nested("https://gitlab.example.com/group/sub/repo.git").unwrap();
+        assert_eq!(l.host.as_deref(), Some("gitlab.example.com"));
+        assert_eq!((l.owner.as_str(), l.repo.as_str()), ("group/sub", "repo"));
+
+        let l = RepoLocation::parse_nested("group/repo").unwrap();
+        assert_eq!((l.owner.as_str(), l.repo.as_str()), ("group", "repo"));
+        assert!(RepoLocation::parse_nested("group//repo").is_err());
+    }
+
+    #[test]
+    fn test_parse_port_and_scheme() {
+        let l = RepoLocation::parse("https://ghe.example.com:8443/abc/docker_image_pusher").unwrap();
+        assert_eq!((l.host.as_deref(), l.port, l.insecure), (Some("ghe.example.com"), Some(8443), false));
+        let urls = l.github_urls().unwrap();
+        assert_eq!(urls.host, "ghe.example.com");
+        assert_eq!(urls.web, "https://ghe.example.com:8443");
+        assert_eq!(urls.api, "https://ghe.example.com:8443/api/v3");
+
+        let l = RepoLocation::parse("http://gitea.local:3000/ops/pusher").unwrap();
+        assert_eq!(l.web_base().as_deref(), Some("http://gitea.local:3000"));
+        let l = RepoLocation::parse("http://gitea.local/ops/pusher").unwrap();
+        assert_eq!(l.web_base().as_deref(), Some("http://gitea.local"));
+
+        // ssh 的端口不是网页的端口
+        let l = RepoLocation::parse("ssh://git@ghe.example.com:2222/abc/docker_image_pusher.git").unwrap();
+        assert_eq!(l.web_base().as_deref(), Some("https://ghe.example.com"));
+        assert_eq!(l.github_urls(), Some(GithubUrls::from_host("ghe.example.com")));
+
+        assert!(RepoLocation::parse("https://ghe.example.com:x/abc/def").is_err());
+        assert_eq!(RepoLocation::parse("abc/def").unwrap().web_base(), None);
+    }
+
+    #[test]
+    fn test_api_base() {
+        assert_eq!(api_base("github.com"), "https://api.github.com");
+        assert_eq!(api_base("ghe.example.com"), "https://ghe.example.com/api/v3");
+    }
+
+    #[test]
+    fn test_resolve_github_urls() {
+        let urls = GithubUrls::resolve("github.com", "", "");
+        assert_eq!((urls.api.as_str(), urls.web.as_str()), ("https://api.github.com", "https://github.com"));
+
+        // http 的 Enterprise Server 只配置网页地址
+        let urls = GithubUrls::resolve("ghe.local", "", "http://ghe.local/");
+        assert_eq!((urls.api.as_str(), urls.web.as_str()), ("http://ghe.local/api/v3", "http://ghe.local"));
+
+        // 代理路径下只配置 api 地址
+        let urls = GithubUrls::resolve("ghe.local", "https://proxy.local/ghe/api/v3", "");
+        assert_eq!(urls.web, "https://proxy.local/ghe");
+        let urls = GithubUrls::resolve("ghe.local", "https://proxy.local/gh-api", "");
+        assert_eq!(urls.web, "https://ghe.local");
+
+        let urls = GithubUrls::resolve("ghe.local", "https://api.proxy.local", "https://proxy.local");
+        assert_eq!((urls.api.as_str(), urls.web.as_str()), ("https://api.proxy.local", "https://proxy.local"));
+
+        assert_eq!(url_host("http://user@ghe.local:8080/ghe"), Some("ghe.local"));
+        assert_eq!(url_host("ghe.local"), None);
+    }
+}
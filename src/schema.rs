@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 
 
@@ -58,3 +60,53 @@ pub struct RepositoryResult {
 	#[serde(rename = "url")]
 	pub url: Option<String>,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TagResult {
+	#[serde(rename = "Tag")]
+	pub tag: Option<String>,
+
+	#[serde(rename = "created")]
+	pub created: Option<String>,
+
+	#[serde(rename = "digest")]
+	pub digest: Option<String>,
+
+	#[serde(rename = "id")]
+	pub id: Option<i64>,
+
+	#[serde(rename = "image_id")]
+	pub image_id: Option<String>,
+
+	#[serde(rename = "internal_path")]
+	pub internal_path: Option<String>,
+
+	#[serde(rename = "path")]
+	pub path: Option<String>,
+
+	#[serde(rename = "repo_id")]
+	pub repo_id: Option<i64>,
+
+	#[serde(rename = "schema")]
+	pub schema: Option<i32>,
+
+	#[serde(rename = "size")]
+	pub size: Option<i64>,
+
+	#[serde(rename = "updated")]
+	pub updated: Option<String>,
+}
+
+/// 生成临时登录指令接口的返回，auths 以镜像仓库地址为键
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuthInfoResult {
+	#[serde(rename = "auths")]
+	pub auths: HashMap<String, AuthResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuthResult {
+	/// base64 编码的 `用户名:密码`
+	#[serde(rename = "auth")]
+	pub auth: String,
+}
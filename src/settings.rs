@@ -1,19 +1,146 @@
-use std::{fs, path::Path};
+use std::{fs, path::{Path, PathBuf}};
 use config::Config;
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    naming::{NamingStrategy, DEFAULT_SEPARATOR},
+    provenance::ProvenanceMode,
+    pusher::{github::GithubApp, Backend},
+    remote::{self, GithubUrls, DEFAULT_GITHUB_HOST},
+};
+
 #[derive(Debug, Deserialize, Serialize,Default)]
 pub struct Settings {
     #[serde(default)]
     pub github_token: String,
     #[serde(default)]
     pub github_pusher_repo: String,
+    /// github app 的 id，设置后以 app 的 installation 身份访问 github，不再使用 github_token
+    #[serde(default)]
+    pub github_app_id: u64,
+    /// github app 私钥(PEM)的路径，配置中只保存路径
+    #[serde(default)]
+    pub github_app_private_key_path: String,
+    /// github app 在 pusher 仓库所属账户上的 installation id
+    #[serde(default)]
+    pub github_app_installation_id: u64,
+    /// pusher 仓库所在的平台，默认 github
+    #[serde(default)]
+    pub pusher_backend: Backend,
+    /// github 主机名，使用 GitHub Enterprise Server 时设置，默认 github.com
+    #[serde(default)]
+    pub github_host: String,
+    /// github api 的完整地址，如 http://ghe.example.com/api/v3 或代理地址，未设置时由网页地址或 github_host 推导
+    #[serde(default)]
+    pub github_api_url: String,
+    /// github 网页的完整地址，如 http://ghe.example.com，未设置时由 api 地址或 github_host 推导
+    #[serde(default)]
+    pub github_web_url: String,
+    /// Gitea/Forgejo 实例地址，如 https://git.example.com，未设置时使用 pusher 地址中的主机
+    #[serde(default)]
+    pub gitea_url: String,
+    /// Gitea/Forgejo 的 access token
+    #[serde(default)]
+    pub gitea_token: String,
+    /// GitLab 实例地址，如 https://gitlab.example.com，未设置时使用 pusher 地址中的主机或 https://gitlab.com
+    #[serde(default)]
+    pub gitlab_url: String,
+    /// GitLab 的 access token，需要 api 权限
+    #[serde(default)]
+    pub gitlab_token: String,
+    /// GitLab 的 pipeline trigger token，未设置时使用 access token 创建 pipeline
+    #[serde(default)]
+    pub gitlab_trigger_token: String,
+    /// GitLab 触发 pipeline 时传递镜像列表的 CI 变量名，默认 IMAGES
+    #[serde(default)]
+    pub gitlab_variable: String,
     #[serde(default)]
     pub ak:String,
     #[serde(default)]
     pub sk:String,
     #[serde(default)]
     pub namespace:String,
+    /// 华为云区域，默认 cn-south-1
+    #[serde(default)]
+    pub region: String,
+    /// 源镜像在 SWR 中的命名方式
+    #[serde(default)]
+    pub naming_strategy: NamingStrategy,
+    /// keep-path 和 registry-prefix 命名时路径各段的连接符，默认 `_`
+    #[serde(default)]
+    pub naming_separator: String,
+    /// pusher 提交 images.txt 时使用的提交者名称，未设置时使用本地 git 配置或 github 用户
+    #[serde(default)]
+    pub commit_author_name: String,
+    /// pusher 提交 images.txt 时使用的提交者邮箱，未设置时使用本地 git 配置或 github noreply 邮箱
+    #[serde(default)]
+    pub commit_author_email: String,
+    /// workflow_dispatch 模式下触发的 workflow 文件名，默认 docker.yaml
+    #[serde(default)]
+    pub pusher_workflow: String,
+    /// workflow_dispatch 模式下传递镜像列表的 input 名称，默认 images
+    #[serde(default)]
+    pub pusher_workflow_input: String,
+    /// 同步记录的保存方式：off、log(mirror-log.jsonl) 或 trailer(提交信息)，默认 off，只有 github 后端的 commit 模式会记录
+    #[serde(default)]
+    pub provenance: ProvenanceMode,
+    /// git 模式下 pusher 仓库的 clone 地址，如 git@github.com:abc/docker_image_pusher.git
+    #[serde(default)]
+    pub pusher_git_url: String,
+}
+
+impl Settings {
+    pub fn region(&self) -> &str {
+        if self.region.is_empty() {
+            "cn-south-1"
+        } else {
+            &self.region
+        }
+    }
+
+    /// 未设置 github_host 时使用网页地址中的主机，都没有时为 github.com
+    pub fn github_host(&self) -> &str {
+        if !self.github_host.is_empty() {
+            &self.github_host
+        } else {
+            remote::url_host(&self.github_web_url).unwrap_or(DEFAULT_GITHUB_HOST)
+        }
+    }
+
+    /// github 的 api 和网页地址，host 为配置的 github 主机时使用配置中的完整地址
+    pub fn github_urls(&self, host: &str) -> GithubUrls {
+        if host == self.github_host() {
+            GithubUrls::resolve(host, &self.github_api_url, &self.github_web_url)
+        } else {
+            GithubUrls::from_host(host)
+        }
+    }
+
+    /// 配置了 app id 时使用 github app 认证，此时必须同时配置 installation id
+    pub fn github_app(&self) -> anyhow::Result<Option<GithubApp>> {
+        if self.github_app_id == 0 {
+            return Ok(None);
+        }
+        if self.github_app_installation_id == 0 {
+            anyhow::bail!(
+                "github_app_id is set but github_app_installation_id is not, \
+                 find it in the url of the app installation settings page"
+            );
+        }
+        Ok(Some(GithubApp {
+            app_id: self.github_app_id,
+            private_key_path: PathBuf::from(&self.github_app_private_key_path),
+            installation_id: self.github_app_installation_id,
+        }))
+    }
+
+    pub fn naming_separator(&self) -> &str {
+        if self.naming_separator.is_empty() {
+            DEFAULT_SEPARATOR
+        } else {
+            &self.naming_separator
+        }
+    }
 }
 
 pub fn load_config(path: &Path) -> anyhow::Result<Settings> {
@@ -31,4 +158,21 @@ pub fn save_config(path: &Path, settings:Settings) -> anyhow::Result<()> {
     fs::write(path, yaml)?;
     println!("Config saved to {}", path.display());
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_github_app() {
+        let mut settings = Settings::default();
+        assert!(settings.github_app().unwrap().is_none());
+
+        settings.github_app_id = 1;
+        let err = settings.github_app().unwrap_err().to_string();
+        assert!(err.contains("github_app_installation_id"), "{err}");
+
+        settings.github_app_installation_id = 42;
+        assert_eq!(settings.github_app().unwrap().unwrap().installation_id, 42);
+    }
+}
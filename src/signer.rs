@@ -1,9 +1,10 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
 use axum::http::{HeaderName, HeaderValue};
 use chrono::{DateTime, Utc};
 use hmac::{Hmac, Mac};
 use hyper::HeaderMap;
+use serde::de::DeserializeOwned;
 use sha2::{Digest, Sha256};
 use url::form_urlencoded;
 
@@ -43,9 +44,25 @@ fn find_header(headers: &HashMap<String, String>, header: &str) -> Option<String
     })
 }
 
+/// 接口返回的非 2xx 响应，调用方可以按状态码区分处理
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: reqwest::StatusCode,
+    pub body: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
 #[derive(Debug)]
 pub struct HttpRequest {
     method: String,
+    #[allow(dead_code)]
     scheme: String,
     host: String,
     uri: String,
@@ -116,6 +133,11 @@ impl HttpRequest {
     }
 
     pub async fn list_repos_details(&self) -> anyhow::Result<Vec<RepositoryResult>> {
+        self.send().await
+    }
+
+    /// 按请求的方法发送签名后的请求，并把成功的响应解析为 JSON，失败时返回 ApiError
+    pub async fn send<T: DeserializeOwned>(&self) -> anyhow::Result<T> {
         let headers: HeaderMap = self
             .headers
             .iter()
@@ -126,10 +148,12 @@ impl HttpRequest {
                 )
             })
             .collect();
+        let method = reqwest::Method::from_bytes(self.method.to_uppercase().as_bytes())?;
         let resp = self
             .http_client
-            .get(&self.url)
+            .request(method, &self.url)
             .headers(headers)
+            .body(self.body.clone())
             .send()
             .await?;
         let status = resp.status();
@@ -137,36 +161,13 @@ impl HttpRequest {
             let text = resp.text().await?;
             Ok(serde_json::from_str(&text)?)
         } else {
-            let text = resp.text().await?;
-            Err(anyhow::anyhow!("{}", text))
+            let body = resp.text().await?;
+            Err(ApiError { status, body }.into())
         }
     }
 
     pub async fn show_repository(&self) -> anyhow::Result<RepositoryResult> {
-        let headers: HeaderMap = self
-            .headers
-            .iter()
-            .map(|(k, v)| {
-                (
-                    HeaderName::from_bytes(k.as_bytes()).unwrap(),
-                    HeaderValue::from_bytes(v.as_bytes()).unwrap(),
-                )
-            })
-            .collect();
-        let resp = self
-            .http_client
-            .get(&self.url)
-            .headers(headers)
-            .send()
-            .await?;
-        let status = resp.status();
-        if status.is_success() {
-            let text = resp.text().await?;
-            Ok(serde_json::from_str(&text)?)
-        } else {
-            let text = resp.text().await?;
-            Err(anyhow::anyhow!("{}", text))
-        }
+        self.send().await
     }
 }
 
@@ -334,6 +335,7 @@ mod test {
     use super::*;
 
     #[tokio::test]
+    #[allow(unused_must_use)]
     async fn test_signer() {
         dotenvy::dotenv().unwrap();
 
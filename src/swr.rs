@@ -0,0 +1,241 @@
+use std::{collections::HashMap, str::FromStr, time::Duration};
+
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{
+    image::ImageReference,
+    naming::ImageMapping,
+    platform::Platform,
+    registry::{Manifest, RegistryClient},
+    schema::{AuthInfoResult, RepositoryResult, TagResult},
+    settings::Settings,
+    signer::{ApiError, HttpRequest, Signer},
+};
+
+/// 轮询 SWR 镜像标签的间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// 列出镜像仓库时每页的数量
+const REPOS_PAGE_SIZE: usize = 100;
+
+/// SWR 管理接口地址
+pub fn api_endpoint(conf: &Settings) -> String {
+    format!("https://swr-api.{}.myhuaweicloud.com", conf.region())
+}
+
+/// SWR 镜像仓库地址
+pub fn registry_host(conf: &Settings) -> String {
+    format!("swr.{}.myhuaweicloud.com", conf.region())
+}
+
+/// docker login 使用的长期有效凭证，用户名为 `区域@AK`，密码为以 SK 为密钥对 AK 做 HMAC-SHA256 的十六进制结果
+pub fn login_credentials(conf: &Settings) -> (String, String) {
+    let mut mac = Hmac::<Sha256>::new_from_slice(conf.sk.as_bytes())
+        .expect("HMAC can take key of any size");
+    mac.update(conf.ak.as_bytes());
+    let password = hex::encode(mac.finalize().into_bytes());
+    (format!("{}@{}", conf.region(), conf.ak), password)
+}
+
+/// 通过生成临时登录指令接口获取 registry 的用户名和密码，有效期为 6 小时
+pub async fn temporary_login(conf: &Settings) -> anyhow::Result<(String, String)> {
+    let url = format!("{}/v2/manage/utils/secret", api_endpoint(conf));
+    let headers = HashMap::from([("content-type".to_string(), "application/json".to_string())]);
+    let mut r = HttpRequest::new("POST", &url, Some(headers), "");
+    Signer.sign(&mut r, &conf.ak, &conf.sk);
+    let info: AuthInfoResult = r.send().await?;
+    let host = registry_host(conf);
+    let auth = info
+        .auths
+        .get(&host)
+        .or_else(|| info.auths.values().next())
+        .with_context(|| format!("SWR returned no login for {host}"))?;
+    decode_auth(&auth.auth)
+}
+
+/// 解码 base64 的 `用户名:密码`
+fn decode_auth(auth: &str) -> anyhow::Result<(String, String)> {
+    let decoded = String::from_utf8(STANDARD.decode(auth)?)?;
+    let (username, password) = decoded
+        .split_once(':')
+        .context("SWR login should be `username:password`")?;
+    Ok((username.to_string(), password.to_string()))
+}
+
+/// 列出命名空间下的所有镜像仓库，按 offset 翻页
+pub async fn list_repositories(conf: &Settings) -> anyhow::Result<Vec<RepositoryResult>> {
+    let mut repositories = Vec::new();
+    loop {
+        let url = format!(
+            "{endpoint}/v2/manage/repos?namespace={namespace}&limit={REPOS_PAGE_SIZE}&offset={offset}",
+            endpoint = api_endpoint(conf),
+            namespace = conf.namespace.as_str(),
+            offset = repositories.len(),
+        );
+        let headers = HashMap::from([("content-type".to_string(), "application/json".to_string())]);
+        let mut r = HttpRequest::new("GET", &url, Some(headers), "");
+        Signer.sign(&mut r, &conf.ak, &conf.sk);
+        let page = r.list_repos_details().await?;
+        let last = page.len() < REPOS_PAGE_SIZE;
+        repositories.extend(page);
+        if last {
+            return Ok(repositories);
+        }
+    }
+}
+
+/// 列出命名空间下某个镜像仓库的所有标签
+pub async fn list_tags(conf: &Settings, repository: &str) -> anyhow::Result<Vec<TagResult>> {
+    let url = format!(
+        "{endpoint}/v2/manage/namespaces/{namespace}/repos/{repository}/tags",
+        endpoint = api_endpoint(conf),
+        namespace = conf.namespace.as_str(),
+        repository = repository.replace('/', "$"),
+    );
+    let headers = HashMap::from([("content-type".to_string(), "application/json".to_string())]);
+    let mut r = HttpRequest::new("GET", &url, Some(headers), "");
+    Signer.sign(&mut r, &conf.ak, &conf.sk);
+    r.send().await
+}
+
+/// 接口返回 404 时为 true，如仓库不存在
+pub fn is_not_found(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<ApiError>()
+        .is_some_and(|e| e.status == reqwest::StatusCode::NOT_FOUND)
+}
+
+/// 源镜像的 manifest digest，多平台镜像额外包含指定平台的 digest，未指定平台时为 linux/amd64
+pub async fn source_digests(image: &ImageReference, platforms: &[Platform]) -> anyhow::Result<Vec<String>> {
+    let manifest = RegistryClient::new()
+        .get_manifest(&image.registry, &image.repository, image.reference())
+        .await?;
+    manifest_digests(&manifest, platforms)
+}
+
+/// manifest 的 digest，多平台镜像额外包含指定平台的 digest，未指定平台时为 linux/amd64
+pub fn manifest_digests(manifest: &Manifest, platforms: &[Platform]) -> anyhow::Result<Vec<String>> {
+    let mut digests = vec![manifest.digest.clone()];
+    if manifest.is_index() {
+        let default = [Platform::from_str("linux/amd64")?];
+        let platforms = if platforms.is_empty() { &default[..] } else { platforms };
+        digests.extend(platforms.iter().filter_map(|p| manifest.platform_digest(p)));
+    }
+    Ok(digests)
+}
+
+/// SWR 中的目标标签已经是源镜像的 digest 时返回该 digest，不需要再次同步
+pub async fn synced_digest(
+    conf: &Settings,
+    mapping: &ImageMapping,
+    platforms: &[Platform],
+) -> anyhow::Result<Option<String>> {
+    let (repository, tag) = mapping.target_repository();
+    let expected = source_digests(&mapping.reference, platforms).await?;
+    let tags = list_tags(conf, &repository).await?;
+    Ok(tag_digest(&tags, &tag).filter(|digest| expected.contains(digest)))
+}
+
+fn tag_digest(tags: &[TagResult], tag: &str) -> Option<String> {
+    tags.iter()
+        .find(|t| t.tag.as_deref() == Some(tag))
+        .and_then(|t| t.digest.clone())
+}
+
+/// SWR 中目标镜像的完整地址
+pub fn target_reference(conf: &Settings, mapping: &ImageMapping) -> String {
+    format!("{}/{}/{}", registry_host(conf), conf.namespace, mapping.target)
+}
+
+/// 等待镜像出现在 SWR 中，并确认 digest 与源镜像一致
+pub async fn verify_sync(
+    conf: &Settings,
+    mapping: &ImageMapping,
+    platforms: &[Platform],
+    timeout: Duration,
+) -> anyhow::Result<TagResult> {
+    let (repository, tag) = mapping.target_repository();
+    let expected = match source_digests(&mapping.reference, platforms).await {
+        Ok(digests) => digests,
+        Err(e) => {
+            cliclack::log::warning(format!("can not resolve source digest, skip digest check: {e}"))?;
+            Vec::new()
+        }
+    };
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    let spinner = cliclack::spinner();
+    spinner.start(format!("waiting for {repository}:{tag} in SWR..."));
+    loop {
+        match list_tags(conf, &repository).await {
+            Ok(tags) => {
+                if let Some(found) = tags.into_iter().find(|t| t.tag.as_deref() == Some(tag.as_str())) {
+                    let digest = found.digest.clone().unwrap_or_default();
+                    if expected.is_empty() || expected.contains(&digest) {
+                        spinner.stop(format!("{repository}:{tag} is in SWR ({digest})"));
+                        return Ok(found);
+                    }
+                    spinner.set_message(format!(
+                        "{repository}:{tag} has digest {digest}, expected {}",
+                        expected.join(" or ")
+                    ));
+                }
+            }
+            Err(e) => spinner.set_message(format!("list SWR tags failed, retrying: {e}")),
+        }
+
+        if tokio::time::Instant::now() >= deadline {
+            spinner.error(format!("{repository}:{tag} did not show up in SWR"));
+            anyhow::bail!(
+                "{repository}:{tag} with digest {} not found in SWR after {}s",
+                if expected.is_empty() { "any".to_string() } else { expected.join(" or ") },
+                timeout.as_secs()
+            );
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tag_digest() {
+        let tags: Vec<TagResult> = serde_json::from_value(serde_json::json!([
+            { "Tag": "1.27", "digest": "sha256:aaa" },
+            { "Tag": "latest", "digest": "sha256:bbb" },
+        ]))
+        .unwrap();
+        assert_eq!(tag_digest(&tags, "latest").as_deref(), Some("sha256:bbb"));
+        assert_eq!(tag_digest(&tags, "1.26"), None);
+    }
+
+    #[test]
+    fn test_decode_auth() {
+        let info: AuthInfoResult = serde_json::from_value(serde_json::json!({
+            "auths": { "swr.cn-south-1.myhuaweicloud.com": { "auth": STANDARD.encode("cn-south-1@AK:token") } }
+        }))
+        .unwrap();
+        let auth = &info.auths["swr.cn-south-1.myhuaweicloud.com"].auth;
+        assert_eq!(
+            decode_auth(auth).unwrap(),
+            ("cn-south-1@AK".to_string(), "token".to_string())
+        );
+        assert!(decode_auth(&STANDARD.encode("no-colon")).is_err());
+    }
+
+    #[test]
+    fn test_is_not_found() {
+        let error = |status| {
+            anyhow::Error::from(ApiError {
+                status,
+                body: r#"{"errorCode":"SVCSTG.SWR.4040002"}"#.to_string(),
+            })
+        };
+        assert!(is_not_found(&error(reqwest::StatusCode::NOT_FOUND)));
+        assert!(!is_not_found(&error(reqwest::StatusCode::UNAUTHORIZED)));
+        assert!(!is_not_found(&anyhow::anyhow!("connection refused")));
+    }
+}
@@ -0,0 +1,126 @@
+use std::{cmp::Ordering, collections::HashSet};
+
+use regex::Regex;
+use semver::{Version, VersionReq};
+
+/// 按 semver 范围或正则表达式从源仓库的标签中选出需要同步的标签
+#[derive(Debug, Default, Clone)]
+pub struct TagSelector {
+    /// semver 范围，如 `~16`、`>=1.25, <1.28`
+    pub range: Option<VersionReq>,
+    /// 标签需要完整匹配的正则表达式
+    pub regex: Option<Regex>,
+    /// 只保留最新的 N 个标签
+    pub latest: Option<usize>,
+}
+
+impl TagSelector {
+    pub fn new(range: Option<&str>, regex: Option<&str>, latest: Option<usize>) -> anyhow::Result<Self> {
+        let range = range
+            .map(|r| VersionReq::parse(r).map_err(|e| anyhow::anyhow!("invalid semver range `{r}`: {e}")))
+            .transpose()?;
+        // 加上 ^ 和 $，避免 `16\..*` 匹配到 `116.1`
+        let regex = regex
+            .map(|r| Regex::new(&format!("^(?:{r})$")).map_err(|e| anyhow::anyhow!("invalid tag regex `{r}`: {e}")))
+            .transpose()?;
+        Ok(Self { range, regex, latest })
+    }
+
+    /// 是否指定了筛选条件，未指定时 sync 按镜像中的标签同步
+    pub fn is_active(&self) -> bool {
+        self.range.is_some() || self.regex.is_some()
+    }
+
+    pub fn matches(&self, tag: &str) -> bool {
+        let range = self
+            .range
+            .as_ref()
+            .is_none_or(|range| tag_version(tag).is_some_and(|v| range.matches(&v)));
+        let regex = self.regex.as_ref().is_none_or(|regex| regex.is_match(tag));
+        range && regex
+    }
+
+    /// 选出匹配的标签，从新到旧排序并取最新的 N 个，再去掉目标中已有的标签
+    pub fn select(&self, tags: &[String], existing: &HashSet<String>) -> Vec<String> {
+        let mut selected = tags
+            .iter()
+            .filter(|tag| self.matches(tag))
+            .map(|tag| (tag_version(tag), tag))
+            .collect::<Vec<_>>();
+        selected.sort_by(|(va, a), (vb, b)| match (va, vb) {
+            (Some(va), Some(vb)) => vb.cmp(va).then_with(|| b.cmp(a)),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => b.cmp(a),
+        });
+        selected
+            .into_iter()
+            .take(self.latest.unwrap_or(usize::MAX))
+            .map(|(_, tag)| tag.clone())
+            .filter(|tag| !existing.contains(tag))
+            .collect()
+    }
+}
+
+/// 把镜像标签解析为 semver，允许 `v` 前缀和省略 patch，如 `16.4`、`v1.2.3`、`16.4-bookworm`
+/// `-` 之后的部分视为 pre-release，只有范围中写了 pre-release 时才会匹配
+/// 只有主版本号的标签如 `16` 会随版本更新移动，不视为版本
+pub fn tag_version(tag: &str) -> Option<Version> {
+    let tag = tag.strip_prefix('v').unwrap_or(tag);
+    let (core, suffix) = match tag.find(['-', '+']) {
+        Some(i) => tag.split_at(i),
+        None => (tag, ""),
+    };
+    let parts = core.split('.').collect::<Vec<_>>();
+    if parts.len() < 2 || parts.len() > 3 || parts.iter().any(|p| p.is_empty() || !p.bytes().all(|b| b.is_ascii_digit())) {
+        return None;
+    }
+    let patch = parts.get(2).copied().unwrap_or("0");
+    Version::parse(&format!("{}.{}.{patch}{suffix}", parts[0], parts[1])).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn test_tag_version() {
+        assert_eq!(tag_version("16.4"), Some(Version::new(16, 4, 0)));
+        assert_eq!(tag_version("v1.2.3"), Some(Version::new(1, 2, 3)));
+        assert_eq!(tag_version("16.4-bookworm").unwrap().pre.as_str(), "bookworm");
+        assert_eq!(tag_version("16"), None);
+        assert_eq!(tag_version("latest"), None);
+        assert_eq!(tag_version("1.2.3.4"), None);
+    }
+
+    #[test]
+    fn test_select_semver() {
+        let upstream = tags(&["15.8", "16", "16.1", "16.2", "16.4", "16.4-bookworm", "16.3-alpine", "17.0", "latest"]);
+        let selector = TagSelector::new(Some("~16"), None, None).unwrap();
+        let existing = HashSet::from(["16.1".to_string()]);
+        assert_eq!(selector.select(&upstream, &existing), tags(&["16.4", "16.2"]));
+
+        // 先取最新的 N 个，再去掉已有的
+        let selector = TagSelector::new(Some("~16"), None, Some(2)).unwrap();
+        let existing = HashSet::from(["16.4".to_string()]);
+        assert_eq!(selector.select(&upstream, &existing), tags(&["16.2"]));
+    }
+
+    #[test]
+    fn test_select_regex() {
+        let upstream = tags(&["16.2-alpine", "16.10-alpine", "116.1-alpine", "16.4"]);
+        let selector = TagSelector::new(None, Some(r"16\.\d+-alpine"), None).unwrap();
+        assert_eq!(selector.select(&upstream, &HashSet::new()), tags(&["16.10-alpine", "16.2-alpine"]));
+
+        let selector = TagSelector::new(None, Some(r".*-alpine"), Some(1)).unwrap();
+        assert_eq!(selector.select(&upstream, &HashSet::new()), tags(&["116.1-alpine"]));
+
+        assert!(TagSelector::new(Some("~x"), None, None).is_err());
+        assert!(TagSelector::new(None, Some("("), None).is_err());
+        assert!(!TagSelector::default().is_active());
+    }
+}
@@ -0,0 +1,256 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use octocrab::{
+    models::workflows::{Conclusion, Run},
+    Octocrab,
+};
+
+/// 失败时输出的日志行数
+pub(crate) const LOG_TAIL_LINES: usize = 40;
+/// 轮询 workflow run 状态的间隔
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 一次推送触发 workflow 的方式，用于找到对应的 workflow run
+#[derive(Debug, Clone)]
+pub enum Trigger {
+    /// 提交 images.txt 产生的 commit sha
+    Commit { sha: String },
+    /// 调用 workflow_dispatch 的 workflow 文件名、分支和调用时间
+    /// head_sha 为调用时分支最新的提交，actor 为调用的账户，github app 等无法确定时为 None
+    Dispatch {
+        workflow: String,
+        branch: String,
+        head_sha: String,
+        actor: Option<String>,
+        since: DateTime<Utc>,
+    },
+    /// 直接创建的 pipeline，如 gitlab 的 pipeline trigger
+    Pipeline { id: u64 },
+}
+
+/// workflow run 失败时的信息
+#[derive(Debug)]
+pub struct RunFailure {
+    /// 失败的运行页面地址
+    pub url: String,
+    pub job: Option<String>,
+    pub step: Option<String>,
+    pub log_tail: Vec<String>,
+}
+
+/// 等待触发的 workflow run 结束，成功返回 run，失败返回失败的 job、step 和日志尾部
+pub async fn wait_for_run(
+    octocrab: &Octocrab,
+    owner: &str,
+    repo: &str,
+    trigger: &Trigger,
+    timeout: Duration,
+) -> anyhow::Result<Result<Run, RunFailure>> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let spinner = cliclack::spinner();
+    spinner.start("waiting for workflow run to start...");
+
+    let mut run_id = None;
+    loop {
+        if tokio::time::Instant::now() >= deadline {
+            spinner.error("timed out waiting for workflow run");
+            anyhow::bail!("timed out after {}s waiting for workflow run", timeout.as_secs());
+        }
+
+        let run = match run_id {
+            Some(id) => Some(octocrab.workflows(owner, repo).get(id).await?),
+            None => find_run(octocrab, owner, repo, trigger).await?,
+        };
+
+        if let Some(run) = run {
+            run_id = Some(run.id);
+            if run.status == "completed" {
+                if run.conclusion.as_deref() == Some("success") {
+                    spinner.stop(format!("workflow run succeeded: {}", run.html_url));
+                    return Ok(Ok(run));
+                }
+                spinner.error(format!(
+                    "workflow run {}: {}",
+                    run.conclusion.as_deref().unwrap_or("failed"),
+                    run.html_url
+                ));
+                return Ok(Err(collect_failure(octocrab, owner, repo, run).await?));
+            }
+            spinner.set_message(format!("workflow run #{} is {}...", run.run_number, run.status));
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// 查找触发对应的 workflow run，还没开始时返回 None
+async fn find_run(
+    octocrab: &Octocrab,
+    owner: &str,
+    repo: &str,
+    trigger: &Trigger,
+) -> anyhow::Result<Option<Run>> {
+    match trigger {
+        Trigger::Commit { sha } => {
+            let runs = octocrab
+                .workflows(owner, repo)
+                .list_all_runs()
+                .event("push")
+                .per_page(20u8)
+                .send()
+                .await?;
+            Ok(runs.items.into_iter().find(|run| &run.head_sha == sha))
+        }
+        Trigger::Dispatch {
+            workflow,
+            branch,
+            head_sha,
+            actor,
+            since,
+        } => {
+            let workflows = octocrab.workflows(owner, repo);
+            let mut builder = workflows
+                .list_runs(workflow.as_str())
+                .event("workflow_dispatch")
+                .branch(branch.as_str())
+                .per_page(20u8);
+            if let Some(actor) = actor {
+                builder = builder.actor(actor.as_str());
+            }
+            let runs = builder.send().await?;
+            // 只认调用账户在同一提交上触发的 run，避免把其他人同时触发的 run 当成自己的
+            // 接口返回的 run 按创建时间倒序排列，取调用之后最早创建的那个
+            let since = *since - chrono::Duration::seconds(5);
+            Ok(runs
+                .items
+                .into_iter()
+                .filter(|run| &run.head_sha == head_sha && run.created_at >= since)
+                .min_by_key(|run| run.created_at))
+        }
+        Trigger::Pipeline { id } => anyhow::bail!("pipeline {id} is not a github workflow run"),
+    }
+}
+
+async fn collect_failure(
+    octocrab: &Octocrab,
+    owner: &str,
+    repo: &str,
+    run: Run,
+) -> anyhow::Result<RunFailure> {
+    let jobs = octocrab
+        .workflows(owner, repo)
+        .list_jobs(run.id)
+        .send()
+        .await?;
+    let failed = jobs
+        .items
+        .into_iter()
+        .find(|job| matches!(job.conclusion, Some(Conclusion::Failure)));
+
+    let Some(job) = failed else {
+        return Ok(RunFailure {
+            url: run.html_url.to_string(),
+            job: None,
+            step: None,
+            log_tail: Vec::new(),
+        });
+    };
+
+    let step = job
+        .steps
+        .iter()
+        .find(|step| matches!(step.conclusion, Some(Conclusion::Failure)));
+
+    let route = format!("/repos/{owner}/{repo}/actions/jobs/{}/logs", job.id);
+    let response = octocrab._get(route).await?;
+    let response = octocrab.follow_location_to_data(response).await?;
+    let log = octocrab.body_to_string(response).await?;
+
+    // 失败的 step 之后还有 post step 和 job 清理的日志，先截取失败 step 的部分
+    let log = match step {
+        Some(step) => step_log(&log, step.started_at, step.completed_at),
+        None => log,
+    };
+
+    Ok(RunFailure {
+        url: run.html_url.to_string(),
+        job: Some(job.name),
+        step: step.map(|step| step.name.clone()),
+        log_tail: log_tail(&log, LOG_TAIL_LINES),
+    })
+}
+
+/// 按 jobs 接口返回的 step 起止时间截取 job 日志中属于该 step 的行
+/// 日志每行以 RFC 3339 时间戳开头，没有时间戳的行属于上一行，没有匹配的行时返回完整日志
+pub(crate) fn step_log(
+    log: &str,
+    started_at: Option<DateTime<Utc>>,
+    completed_at: Option<DateTime<Utc>>,
+) -> String {
+    // step 的起止时间只精确到秒，日志的时间戳精确到 100ns
+    let start = started_at.map(|t| t.timestamp());
+    let end = completed_at.map(|t| t.timestamp());
+    let mut in_step = false;
+    let lines = log
+        .lines()
+        .filter(|line| {
+            let timestamp = line
+                .split_once(' ')
+                .and_then(|(t, _)| DateTime::parse_from_rfc3339(t).ok())
+                .map(|t| t.timestamp());
+            if let Some(t) = timestamp {
+                in_step = start.is_none_or(|s| t >= s) && end.is_none_or(|e| t <= e);
+            }
+            in_step
+        })
+        .collect::<Vec<_>>();
+    if lines.is_empty() {
+        return log.to_string();
+    }
+    lines.join("\n")
+}
+
+/// 取日志的最后 n 行
+pub(crate) fn log_tail(log: &str, n: usize) -> Vec<String> {
+    let lines = log.lines().collect::<Vec<_>>();
+    lines[lines.len().saturating_sub(n)..]
+        .iter()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_log_tail() {
+        let log = "a\nb\nc\nd";
+        assert_eq!(log_tail(log, 2), vec!["c".to_string(), "d".to_string()]);
+        assert_eq!(log_tail(log, 10).len(), 4);
+    }
+
+    #[test]
+    fn test_step_log() {
+        let log = "\
+2024-12-01T08:00:01.1000000Z ##[group]Run actions/checkout@v4
+2024-12-01T08:00:02.2000000Z checked out
+2024-12-01T08:00:03.3000000Z ##[group]Run docker pull nginx:1.27
+2024-12-01T08:00:04.4000000Z Error response from daemon:
+  manifest unknown
+2024-12-01T08:00:05.5000000Z ##[error]Process completed with exit code 1.
+2024-12-01T08:00:07.1000000Z Post job cleanup.
+2024-12-01T08:00:08.1000000Z Cleaning up orphan processes";
+        let at = |s: &str| Some(s.parse::<DateTime<Utc>>().unwrap());
+        let step = step_log(log, at("2024-12-01T08:00:03Z"), at("2024-12-01T08:00:05Z"));
+        let lines = step.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].ends_with("Run docker pull nginx:1.27"));
+        assert_eq!(lines[2], "  manifest unknown");
+        assert!(lines[3].ends_with("exit code 1."));
+
+        // 时间对不上时返回完整日志
+        assert_eq!(step_log(log, at("2024-12-02T00:00:00Z"), None), log);
+    }
+}